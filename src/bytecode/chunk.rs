@@ -1,9 +1,28 @@
 use crate::{errors::ReloxError, Result};
-use std::usize;
+use std::rc::Rc;
 
+use super::object::ObjString;
 use super::value::Value;
 
 /// Bytecode Op Codes
+///
+/// `Modulo`, `Power` and the bitwise operators pop two operands and push the result, like
+/// `Add`. `BitNot` flips the bits of the integer on top of the stack.
+///
+/// Jump offsets count instructions from the one after the jump: `Jump` and `JumpIfFalse` go
+/// forward, `Loop` goes backwards. `JumpIfFalse` leaves the condition on the stack.
+///
+/// `Call` expects the callee on the stack below its `arg_count` arguments. `Closure` wraps the
+/// function constant in a closure, capturing the upvalues listed in the function. `Method`
+/// adds the closure on top of the stack to the class right below it. `Inherit` copies the
+/// methods of the superclass below the subclass on top of the stack. `BuildList` collects the
+/// top `item_count` values into a list and `BuildMap` the top `entry_count` key value pairs into
+/// a map. `GetIndex` and `SetIndex` work on both. `Throw` unwinds to the closest handler of the
+/// value on top of the stack. `ToString` replaces the value on top of the stack with its string
+/// form, it's used by string interpolation.
+///
+/// `Import` pushes the module compiled into the function constant, running it first if it's
+/// the first import. Modules end by returning themselves with `CurrentModule`.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum OpCode {
     Constant { constant_offset: u8 },
@@ -13,21 +32,75 @@ pub enum OpCode {
     Substract,
     Divide,
     Multiply,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
     Nil,
     True,
     False,
     Not,
+    Equal,
+    Greater,
+    Less,
+    Pop,
+    Print,
+    DefineGlobal { constant_offset: u8 },
+    GetGlobal { constant_offset: u8 },
+    SetGlobal { constant_offset: u8 },
+    GetLocal { slot: u8 },
+    SetLocal { slot: u8 },
+    Jump { offset: u16 },
+    JumpIfFalse { offset: u16 },
+    Loop { offset: u16 },
+    Call { arg_count: u8 },
+    Closure { constant_offset: u8 },
+    GetUpvalue { slot: u8 },
+    SetUpvalue { slot: u8 },
+    CloseUpvalue,
+    Class { constant_offset: u8 },
+    GetProperty { constant_offset: u8 },
+    SetProperty { constant_offset: u8 },
+    Method { constant_offset: u8 },
+    Inherit,
+    GetSuper { constant_offset: u8 },
+    BuildList { item_count: u8 },
+    BuildMap { entry_count: u8 },
+    GetIndex,
+    SetIndex,
+    Throw,
+    ToString,
+    Import { constant_offset: u8 },
+    CurrentModule,
 }
 const CONSTANT_POOL_MAX: usize = 255;
 
+/// Where to go when an exception is thrown by the instructions in `start..end`. The stack is
+/// cut down to `stack_depth` slots of the frame and the exception is pushed before jumping to
+/// `target`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceptionHandler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub stack_depth: usize,
+}
+
 /// Chunk
 ///
-/// Represents a chunk of Opcodes. It can be thought as an array of bytes
+/// Represents a chunk of Opcodes. It can be thought as an array of bytes. Handlers are
+/// added once their protected code is compiled, so nested handlers come before the ones
+/// around them.
 #[derive(Debug)]
 pub struct Chunk {
     code: Vec<OpCode>,
     constant_pool: Vec<Value>,
     lines: Vec<u16>,
+    handlers: Vec<ExceptionHandler>,
 }
 
 impl Chunk {
@@ -36,13 +109,10 @@ impl Chunk {
             code: Vec::new(),
             constant_pool: Vec::new(),
             lines: Vec::new(),
+            handlers: Vec::new(),
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.code.is_empty()
-    }
-
     pub fn instruction_at(&self, index: usize) -> Option<&OpCode> {
         self.code.get(index)
     }
@@ -51,6 +121,10 @@ impl Chunk {
         *self.lines.get(index).unwrap_or(&0)
     }
 
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
     pub fn read_constant(&self, index: u8) -> Option<&Value> {
         self.constant_pool.get(index as usize)
     }
@@ -61,14 +135,79 @@ impl Chunk {
     }
 
     pub fn add_constant(&mut self, constant: Value, line: u16) -> Result<()> {
+        let constant_offset = self.make_constant(constant)?;
+        self.write_bytecode(OpCode::Constant { constant_offset }, line);
+        Ok(())
+    }
+
+    /// Adds a value to the constant pool without emitting any instruction and returns its offset
+    pub fn make_constant(&mut self, constant: Value) -> Result<u8> {
         if self.constant_pool.len() >= CONSTANT_POOL_MAX {
             return ReloxError::new_fatal_error("Constant Pool max reached".to_string());
         }
 
         self.constant_pool.push(constant);
+        Ok((self.constant_pool.len() - 1) as u8)
+    }
+
+    /// Adds an interned identifier name (e.g. a global variable) to the constant pool and
+    /// returns its offset. Names already in the pool are reused so every use of a global
+    /// shares one slot.
+    pub fn add_identifier(&mut self, name: Rc<ObjString>) -> Result<u8> {
+        let existing = self
+            .constant_pool
+            .iter()
+            .position(|constant| match constant {
+                Value::String(constant) => Rc::ptr_eq(constant, &name),
+                _ => false,
+            });
+
+        match existing {
+            Some(offset) => Ok(offset as u8),
+            None => self.make_constant(Value::String(name)),
+        }
+    }
+
+    /// Back-patches the jump at `index` so it lands on the next instruction to be written
+    pub fn patch_jump(&mut self, index: usize) -> Result<()> {
+        let jump_length = self.code.len() - index - 1;
+
+        if jump_length > u16::MAX as usize {
+            return ReloxError::new_fatal_error("Too much code to jump over".to_string());
+        }
+        let jump_length = jump_length as u16;
+
+        match self.code.get_mut(index) {
+            Some(OpCode::Jump { offset }) | Some(OpCode::JumpIfFalse { offset }) => {
+                *offset = jump_length;
+                Ok(())
+            }
+            _ => ReloxError::new_fatal_error(format!("There's no jump to patch at {}", index)),
+        }
+    }
+
+    pub fn add_handler(&mut self, handler: ExceptionHandler) {
+        self.handlers.push(handler);
+    }
+
+    /// The innermost handler protecting the instruction at `index`
+    pub fn handler_for(&self, index: usize) -> Option<&ExceptionHandler> {
+        self.handlers
+            .iter()
+            .find(|handler| handler.start <= index && index < handler.end)
+    }
+
+    /// Writes a loop that jumps back to the instruction at `loop_start`
+    pub fn write_loop(&mut self, loop_start: usize, line: u16) -> Result<()> {
+        let jump_length = self.code.len() - loop_start + 1;
+
+        if jump_length > u16::MAX as usize {
+            return ReloxError::new_fatal_error("Loop body too large".to_string());
+        }
+
         self.write_bytecode(
-            OpCode::Constant {
-                constant_offset: (self.constant_pool.len() - 1) as u8,
+            OpCode::Loop {
+                offset: jump_length as u16,
             },
             line,
         );
@@ -77,7 +216,7 @@ impl Chunk {
 
     pub fn dissasemble(&self) {
         println!("== Dissasembling Chunk ==");
-        println!("byte_offset   lines   op    data");
+        println!("index   byte_offset   lines   op    data");
         println!();
 
         let mut byte_offset = 0;
@@ -85,21 +224,52 @@ impl Chunk {
         for (i, opcode) in self.code.iter().enumerate() {
             self.dissasemble_instruction(opcode, i, &mut byte_offset)
         }
+
+        for handler in self.handlers.iter() {
+            println!(
+                "handler {}..{} -> {} (stack depth {})",
+                handler.start, handler.end, handler.target, handler.stack_depth
+            );
+        }
     }
 
     pub fn dissasemble_instruction(&self, opcode: &OpCode, i: usize, byte_offset: &mut usize) {
-        let extra_chunk = match opcode {
-            OpCode::Constant { constant_offset } => {
-                format!("{:?}", self.constant_pool[*constant_offset as usize])
-            }
-            _ => "".to_string(),
-        };
         println!(
-            "{:?}             {:?}      {:?}    {}",
-            byte_offset, self.lines[i], opcode, extra_chunk
+            "{:?}       {:?}             {:?}      {:?}    {}",
+            i,
+            byte_offset,
+            self.line_at(i),
+            opcode,
+            self.instruction_data(opcode, i)
         );
         *byte_offset += std::mem::size_of_val(opcode);
     }
+
+    fn instruction_data(&self, opcode: &OpCode, i: usize) -> String {
+        match opcode {
+            OpCode::Constant { constant_offset }
+            | OpCode::DefineGlobal { constant_offset }
+            | OpCode::GetGlobal { constant_offset }
+            | OpCode::SetGlobal { constant_offset }
+            | OpCode::Closure { constant_offset }
+            | OpCode::Class { constant_offset }
+            | OpCode::GetProperty { constant_offset }
+            | OpCode::SetProperty { constant_offset }
+            | OpCode::Method { constant_offset }
+            | OpCode::GetSuper { constant_offset }
+            | OpCode::Import { constant_offset } => {
+                format!("{:?}", self.constant_pool[*constant_offset as usize])
+            }
+            OpCode::Jump { offset } | OpCode::JumpIfFalse { offset } => {
+                format!("-> {}", i + 1 + *offset as usize)
+            }
+            OpCode::Loop { offset } => format!("-> {}", i + 1 - *offset as usize),
+            OpCode::Call { arg_count } => format!("({} args)", arg_count),
+            OpCode::BuildList { item_count } => format!("({} items)", item_count),
+            OpCode::BuildMap { entry_count } => format!("({} entries)", entry_count),
+            _ => "".to_string(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -108,24 +278,90 @@ mod tests {
     use std::mem::size_of_val;
 
     use super::*;
+    use crate::bytecode::interner::Interner;
 
     #[test]
     fn test_chunk() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(3.0), 22).unwrap();
-        chunk.add_constant(Value::Number(4.0), 22).unwrap();
+        chunk.add_constant(Value::Float(3.0), 22).unwrap();
+        chunk.add_constant(Value::Float(4.0), 22).unwrap();
         chunk.write_bytecode(OpCode::Add, 22);
         chunk.write_bytecode(OpCode::Return, 23);
 
         assert_eq!(24, size_of_val(&chunk.code));
         assert_eq!(24, size_of_val(&chunk.constant_pool));
         assert_eq!(24, size_of_val(&chunk.lines));
-        assert_eq!(72, size_of_val(&chunk));
-        assert_eq!(8, size_of_val(&Value::Bool(true)));
-        assert_eq!(8, size_of_val(&Value::Number(3.3)));
-        assert_eq!(2, size_of_val(&OpCode::Return));
-        assert_eq!(2, size_of_val(&OpCode::True));
-        assert_eq!(8, size_of_val(&Value::Number(3.3)));
+        assert_eq!(96, size_of_val(&chunk));
+        assert_eq!(16, size_of_val(&Value::Bool(true)));
+        assert_eq!(16, size_of_val(&Value::Float(3.3)));
+        assert_eq!(4, size_of_val(&OpCode::Return));
+        assert_eq!(4, size_of_val(&OpCode::True));
+        assert_eq!(16, size_of_val(&Value::Float(3.3)));
         // chunk.dissasemble();
     }
+
+    #[test]
+    fn test_patch_jump() {
+        let mut chunk = Chunk::new();
+        chunk.write_bytecode(OpCode::True, 1);
+        chunk.write_bytecode(OpCode::JumpIfFalse { offset: 0 }, 1);
+        chunk.write_bytecode(OpCode::Pop, 1);
+        chunk.write_bytecode(OpCode::Nil, 1);
+        chunk.patch_jump(1).unwrap();
+
+        let jump = *chunk.instruction_at(1).unwrap();
+        assert_eq!(OpCode::JumpIfFalse { offset: 2 }, jump);
+        assert_eq!("-> 4", chunk.instruction_data(&jump, 1));
+        assert!(chunk.patch_jump(2).is_err());
+    }
+
+    #[test]
+    fn test_write_loop() {
+        let mut chunk = Chunk::new();
+        chunk.write_bytecode(OpCode::Nil, 1);
+        chunk.write_bytecode(OpCode::Pop, 1);
+        chunk.write_loop(0, 1).unwrap();
+
+        let loop_op = *chunk.instruction_at(2).unwrap();
+        assert_eq!(OpCode::Loop { offset: 3 }, loop_op);
+        assert_eq!("-> 0", chunk.instruction_data(&loop_op, 2));
+    }
+
+    #[test]
+    fn test_innermost_handler_wins() {
+        let mut chunk = Chunk::new();
+        let inner = ExceptionHandler {
+            start: 2,
+            end: 4,
+            target: 5,
+            stack_depth: 2,
+        };
+        let outer = ExceptionHandler {
+            start: 0,
+            end: 6,
+            target: 7,
+            stack_depth: 1,
+        };
+        chunk.add_handler(inner);
+        chunk.add_handler(outer);
+
+        assert_eq!(Some(&outer), chunk.handler_for(0));
+        assert_eq!(Some(&inner), chunk.handler_for(3));
+        assert_eq!(Some(&outer), chunk.handler_for(4));
+        assert_eq!(None, chunk.handler_for(6));
+    }
+
+    #[test]
+    fn test_identifiers_are_deduplicated() {
+        let mut interner = Interner::new();
+        let mut chunk = Chunk::new();
+        chunk.add_constant(Value::Float(1.0), 1).unwrap();
+        assert_eq!(1, chunk.add_identifier(interner.intern("a")).unwrap());
+        assert_eq!(2, chunk.add_identifier(interner.intern("b")).unwrap());
+        assert_eq!(1, chunk.add_identifier(interner.intern("a")).unwrap());
+
+        // A string constant with the same content but not interned isn't reused
+        let not_interned = Rc::new(ObjString::new("b".to_string()));
+        assert_eq!(3, chunk.add_identifier(not_interned).unwrap());
+    }
 }
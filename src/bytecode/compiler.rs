@@ -1,11 +1,18 @@
-use super::chunk::{Chunk, OpCode};
+use super::chunk::{Chunk, ExceptionHandler, OpCode};
+use super::interner::Interner;
+use super::object::{ObjFunction, UpvalueDescriptor};
 use super::value::Value;
 use crate::errors::ErrorKind::ParserError;
 use crate::errors::{ErrorKind::Fatal, ReloxError};
+use crate::scanner::Scanner;
 use crate::token::token_type::TokenType;
 use crate::token::Literal;
 use crate::token::Token;
 use crate::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug)]
 enum Precedence {
@@ -15,11 +22,15 @@ enum Precedence {
     And,        // and
     Equality,   // == !=
     Comparison, // < > <= >=
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
+    Shift,      // << >>
     Term,       // + -
-    Factor,     // * /
-    Unary,      // ! -
+    Factor,     // * / %
+    Unary,      // ! - ~
+    Power,      // **
     Call,       // . ()
-    Primary,
 }
 
 impl Precedence {
@@ -27,8 +38,26 @@ impl Precedence {
         match token_type {
             TokenType::Slash => Precedence::Factor,
             TokenType::Star => Precedence::Factor,
+            TokenType::Percent => Precedence::Factor,
+            TokenType::StarStar => Precedence::Power,
+            TokenType::LessLess => Precedence::Shift,
+            TokenType::GreaterGreater => Precedence::Shift,
+            TokenType::Ampersand => Precedence::BitAnd,
+            TokenType::Caret => Precedence::BitXor,
+            TokenType::Pipe => Precedence::BitOr,
             TokenType::Minus => Precedence::Term,
             TokenType::Plus => Precedence::Term,
+            TokenType::BangEqual => Precedence::Equality,
+            TokenType::EqualEqual => Precedence::Equality,
+            TokenType::Greater => Precedence::Comparison,
+            TokenType::GreaterEqual => Precedence::Comparison,
+            TokenType::Less => Precedence::Comparison,
+            TokenType::LessEqual => Precedence::Comparison,
+            TokenType::And => Precedence::And,
+            TokenType::Or => Precedence::Or,
+            TokenType::LeftParen => Precedence::Call,
+            TokenType::Dot => Precedence::Call,
+            TokenType::LeftBracket => Precedence::Call,
             _ => Precedence::None,
         }
     }
@@ -41,37 +70,182 @@ impl Precedence {
             Precedence::And => 4,
             Precedence::Equality => 5,
             Precedence::Comparison => 6,
-            Precedence::Term => 7,
-            Precedence::Factor => 8,
-            Precedence::Unary => 9,
-            Precedence::Call => 10,
-            Precedence::Primary => 11,
+            Precedence::BitOr => 7,
+            Precedence::BitXor => 8,
+            Precedence::BitAnd => 9,
+            Precedence::Shift => 10,
+            Precedence::Term => 11,
+            Precedence::Factor => 12,
+            Precedence::Unary => 13,
+            Precedence::Power => 14,
+            Precedence::Call => 15,
         }
     }
 }
 
+const LOCALS_MAX: usize = 256;
+const UPVALUES_MAX: usize = 256;
+
+/// A local variable living in a stack slot. `depth` is `None` while its initializer is
+/// being compiled, so the variable can't be read in its own initializer. Captured locals are
+/// closed instead of popped when their scope ends.
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: Option<usize>,
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionType {
+    Script,
+    Module,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// A loop being compiled. `continue` jumps back to `continue_target` and the `break` jumps are
+/// patched once the end of the loop is known. Both discard the locals deeper than `scope_depth`.
+#[derive(Debug)]
+struct LoopState {
+    label: Option<String>,
+    continue_target: usize,
+    scope_depth: usize,
+    break_jumps: Vec<usize>,
+}
+
+/// The `finally` of a `try` being compiled. `break`, `continue` and `return` compile the block
+/// again, from the tokens starting at `cursor`, before jumping out of the `try`. `loop_depth` is
+/// the number of loops around the `try` and `try_depth` the number of tries.
+#[derive(Debug, Clone, Copy)]
+struct FinallyBlock {
+    cursor: usize,
+    loop_depth: usize,
+    try_depth: usize,
+}
+
+/// A finally block inlined by a jump. It runs outside of its `try`, so the handlers of the
+/// tries deeper than `try_depth` don't protect it.
+#[derive(Debug, Clone, Copy)]
+struct InlinedFinally {
+    start: usize,
+    end: usize,
+    try_depth: usize,
+}
+
+/// The function being compiled along with its locals. Slot 0 is reserved for the function
+/// being called, or for `this` in methods, so the first declared local lives in slot 1.
+#[derive(Debug)]
+struct FunctionState {
+    function: ObjFunction,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    scope_depth: usize,
+    loops: Vec<LoopState>,
+    finally_blocks: Vec<FinallyBlock>,
+    try_depth: usize,
+    inlined_finally_blocks: Vec<InlinedFinally>,
+}
+
+impl FunctionState {
+    fn new(function: ObjFunction, function_type: FunctionType) -> Self {
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Script | FunctionType::Module | FunctionType::Function => "",
+        };
+
+        Self {
+            function,
+            function_type,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            scope_depth: 0,
+            loops: Vec::new(),
+            finally_blocks: Vec::new(),
+            try_depth: 0,
+            inlined_finally_blocks: Vec::new(),
+        }
+    }
+}
+
+/// The class being compiled, used to tell whether `this` and `super` are valid
+#[derive(Debug)]
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// The modules compiled so far, shared by the compilers of every imported file. `importing`
+/// is the chain of files being compiled, with the path they were imported as.
+#[derive(Debug, Default)]
+struct Modules {
+    compiled: HashMap<PathBuf, Rc<ObjFunction>>,
+    importing: Vec<(PathBuf, String)>,
+}
+
 #[derive(Debug)]
-pub struct Compiler {
-    chunk: Chunk,
+struct FirstError {
+    line: usize,
+    message: String,
+    where_it_was: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Compiler<'a> {
+    current: FunctionState,
+    enclosing: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    interner: &'a mut Interner,
     tokens: Vec<Token>,
     cursor: usize,
     had_error: bool,
     panic_mode: bool,
+    first_error: Option<FirstError>,
+    has_trailing_expression: bool,
+    declaration_start: usize,
+    path: Option<PathBuf>,
+    modules: Modules,
 }
 
-impl Compiler {
-    pub fn run_with(tokens: Vec<Token>) -> Result<Chunk> {
-        Self::new(tokens).compile()
+impl<'a> Compiler<'a> {
+    /// Compiles the tokens into a chunk. Strings are interned in the given interner, which
+    /// should be the one owned by the vm that will run the chunk.
+    pub fn run_with(tokens: Vec<Token>, interner: &'a mut Interner) -> Result<Chunk> {
+        Self::new(tokens, interner).compile()
     }
 
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<Token>, interner: &'a mut Interner) -> Self {
         Self {
-            chunk: Chunk::new(),
+            current: FunctionState::new(ObjFunction::new(None), FunctionType::Script),
+            enclosing: Vec::new(),
+            classes: Vec::new(),
+            interner,
             tokens,
             cursor: 0,
             had_error: false,
             panic_mode: false,
+            first_error: None,
+            has_trailing_expression: false,
+            declaration_start: 0,
+            path: None,
+            modules: Modules::default(),
+        }
+    }
+
+    /// Compiles the tokens of the file at `path`. Imports are resolved relative to it.
+    pub fn run_file(tokens: Vec<Token>, interner: &'a mut Interner, path: &Path) -> Result<Chunk> {
+        let mut compiler = Self::new(tokens, interner);
+        if let Ok(canonical) = path.canonicalize() {
+            compiler
+                .modules
+                .importing
+                .push((canonical, path.display().to_string()));
         }
+        compiler.path = Some(path.to_path_buf());
+        compiler.compile()
     }
 
     pub fn compile(mut self) -> Result<Chunk> {
@@ -88,298 +262,2937 @@ impl Compiler {
 
         self.emit_return()?;
 
-        Ok(self.chunk)
+        Ok(self.current.function.chunk)
     }
 
     pub fn parse(&mut self) -> Result<()> {
-        self.expression()?;
-        let res = self.consume(TokenType::Eof, "Expects an expression");
+        while !self.matches(TokenType::Eof)? {
+            self.declaration()?;
+        }
 
-        if self.had_error {
-            let current_token = self.current_token()?;
-            ReloxError::new_compile_error(
-                current_token.line,
-                "Compilation Error".to_string(),
-                None,
-                ParserError,
-            )
+        match self.first_error.take() {
+            Some(FirstError {
+                line,
+                message,
+                where_it_was,
+            }) => ReloxError::new_compile_error(line, message, where_it_was, ParserError),
+            None => Ok(()),
+        }
+    }
+
+    // == Statements ==
+    fn declaration(&mut self) -> Result<()> {
+        self.declaration_start = self.cursor;
+        if self.matches(TokenType::Class)? {
+            self.class_declaration()?;
+        } else if self.matches(TokenType::Fun)? {
+            self.fun_declaration()?;
+        } else if self.matches(TokenType::Var)? {
+            self.var_declaration()?;
         } else {
-            res
+            self.statement()?;
+        }
+
+        if self.panic_mode {
+            self.synchronize()?;
         }
+        Ok(())
     }
 
-    fn advance(&mut self) -> Result<()> {
-        loop {
-            let token = self.current_token()?;
+    fn class_declaration(&mut self) -> Result<()> {
+        let global = self.parse_variable("Expect class name")?;
+        let class_name = self.prev_token()?.clone();
+        let constant_offset = self.identifier_constant(&class_name.lexeme)?;
 
-            if token.token_type == TokenType::ErrorToken {
-                let token_cl = token.clone();
-                self.handle_error(token_cl, "");
-            } else {
-                break;
+        self.emit_byte(OpCode::Class { constant_offset })?;
+        self.define_variable(global)?;
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if self.matches(TokenType::Less)? {
+            self.consume(TokenType::Identifier, "Expect superclass name")?;
+            let superclass_name = self.prev_token()?.clone();
+            self.variable(false)?;
+
+            if superclass_name.lexeme == class_name.lexeme {
+                self.handle_error(superclass_name.clone(), "A class can't inherit from itself");
+            }
+
+            // The superclass is kept in a local named `super` that methods capture
+            self.begin_scope();
+            self.add_local(Self::synthetic_token("super", &superclass_name));
+            self.mark_initialized();
+
+            self.named_variable(&class_name, false)?;
+            self.emit_byte(OpCode::Inherit)?;
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
             }
         }
 
-        self.cursor += 1;
-        Ok(())
+        // The class is left on the stack while its methods are added to it
+        self.named_variable(&class_name, false)?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body")?;
+        while !self.check(TokenType::RightBrace)? && !self.check(TokenType::Eof)? {
+            self.method()?;
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body")?;
+        self.emit_byte(OpCode::Pop)?;
+
+        match self.classes.pop() {
+            Some(ClassState {
+                has_superclass: true,
+            }) => self.end_scope(),
+            _ => Ok(()),
+        }
     }
 
-    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<()> {
-        let token = self.current_token()?;
+    fn method(&mut self) -> Result<()> {
+        self.consume(TokenType::Identifier, "Expect method name")?;
+        let name = self.prev_token()?.lexeme.clone();
+        let constant_offset = self.identifier_constant(&name)?;
 
-        if token.token_type == token_type {
-            self.advance()?;
-        } else {
-            let token = token.clone();
-            self.handle_error(token, message)
-        }
-        Ok(())
+        let function_type = match name.as_str() {
+            "init" => FunctionType::Initializer,
+            _ => FunctionType::Method,
+        };
+        self.function(function_type)?;
+        self.emit_byte(OpCode::Method { constant_offset })
     }
 
-    fn binary(&mut self, token_type: TokenType) -> Result<()> {
-        let higher_precedence = Precedence::new(self.current_token_type()?).to_number() + 1;
-        self.parse_with_precendece(higher_precedence)?;
+    fn fun_declaration(&mut self) -> Result<()> {
+        let global = self.parse_variable("Expect function name")?;
+        // Marked before compiling the body so the function can call itself recursively
+        self.mark_initialized();
+        self.function(FunctionType::Function)?;
+        self.define_variable(global)
+    }
 
-        match token_type {
-            TokenType::Plus => self.emit_byte(OpCode::Add),
-            TokenType::Minus => self.emit_byte(OpCode::Substract),
-            TokenType::Star => self.emit_byte(OpCode::Multiply),
-            TokenType::Slash => self.emit_byte(OpCode::Divide),
-            _ => Ok(()),
+    /// Compiles a function's parameters and body into a new function object, which is then
+    /// loaded as a constant in the enclosing chunk.
+    fn function(&mut self, function_type: FunctionType) -> Result<()> {
+        let name = self.prev_token()?.lexeme.clone();
+        let function = ObjFunction::new(Some(self.interner.intern_owned(name)));
+        let state = FunctionState::new(function, function_type);
+        self.enclosing
+            .push(std::mem::replace(&mut self.current, state));
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name")?;
+        if !self.check(TokenType::RightParen)? {
+            loop {
+                if self.current.function.arity == u8::MAX {
+                    let token = self.current_token()?.clone();
+                    self.handle_error(token, "Can't have more than 255 parameters");
+                } else {
+                    self.current.function.arity += 1;
+                }
+
+                let parameter = self.parse_variable("Expect parameter name")?;
+                self.define_variable(parameter)?;
+
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
         }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body")?;
+        self.block()?;
+
+        let function = self.end_function()?;
+        let constant_offset = self
+            .chunk()
+            .make_constant(Value::Function(Rc::new(function)))?;
+        self.emit_byte(OpCode::Closure { constant_offset })
     }
 
-    fn parse_with_precendece(&mut self, precedence: u8) -> Result<()> {
-        self.advance()?;
+    fn end_function(&mut self) -> Result<ObjFunction> {
+        self.emit_return()?;
 
-        // dbg!(self.prev_token()?, self.current_token()?, self.cursor);
+        let enclosing = self.enclosing.pop().ok_or_else(|| {
+            ReloxError::new_unwrapped_fatal_error("No enclosing function to return to".to_string())
+        })?;
+        Ok(std::mem::replace(&mut self.current, enclosing).function)
+    }
 
-        self.parse_prefix_for_type(self.prev_token_type()?)?;
+    fn var_declaration(&mut self) -> Result<()> {
+        let global = self.parse_variable("Expect variable name")?;
 
-        while precedence <= Precedence::new(self.current_token_type()?).to_number() {
-            self.advance()?;
-            self.parse_infix_for_type(self.prev_token_type()?)?
+        if self.matches(TokenType::Equal)? {
+            self.expression()?;
+        } else {
+            self.emit_byte(OpCode::Nil)?;
         }
-        Ok(())
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration",
+        )?;
+        self.define_variable(global)
     }
 
-    fn expression(&mut self) -> Result<()> {
-        self.parse_with_precendece(Precedence::Assignment.to_number())
+    fn statement(&mut self) -> Result<()> {
+        if self.matches(TokenType::Print)? {
+            self.print_statement()
+        } else if self.matches(TokenType::If)? {
+            self.if_statement()
+        } else if self.matches(TokenType::While)? {
+            self.while_statement(None)
+        } else if self.matches(TokenType::For)? {
+            self.for_statement(None)
+        } else if self.check(TokenType::Identifier)? && self.next_token_type()? == TokenType::Colon
+        {
+            self.labeled_statement()
+        } else if self.matches(TokenType::Break)? {
+            self.break_statement()
+        } else if self.matches(TokenType::Continue)? {
+            self.continue_statement()
+        } else if self.matches(TokenType::Return)? {
+            self.return_statement()
+        } else if self.matches(TokenType::Try)? {
+            self.try_statement()
+        } else if self.matches(TokenType::Throw)? {
+            self.throw_statement()
+        } else if self.matches(TokenType::Import)? {
+            self.import_statement()
+        } else if self.matches(TokenType::LeftBrace)? {
+            self.begin_scope();
+            self.block()?;
+            self.end_scope()
+        } else {
+            self.expression_statement()
+        }
     }
 
-    fn number(&mut self) -> Result<()> {
-        self.emit_constant()
+    fn block(&mut self) -> Result<()> {
+        while !self.check(TokenType::RightBrace)? && !self.check(TokenType::Eof)? {
+            self.declaration()?;
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block")
     }
 
-    fn literal(&mut self, token_type: TokenType) -> Result<()> {
-        match token_type {
-            TokenType::Nil => self.emit_byte(OpCode::Nil),
-            TokenType::True => self.emit_byte(OpCode::True),
-            TokenType::False => self.emit_byte(OpCode::False),
-            _ => Ok(()),
+    fn if_statement(&mut self) -> Result<()> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'")?;
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition")?;
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse { offset: 0 })?;
+        self.emit_byte(OpCode::Pop)?;
+        self.statement()?;
+
+        let else_jump = self.emit_jump(OpCode::Jump { offset: 0 })?;
+        self.chunk().patch_jump(then_jump)?;
+        self.emit_byte(OpCode::Pop)?;
+
+        if self.matches(TokenType::Else)? {
+            self.statement()?;
         }
+        self.chunk().patch_jump(else_jump)
     }
 
-    fn grouping(&mut self) -> Result<()> {
+    fn while_statement(&mut self, label: Option<String>) -> Result<()> {
+        let loop_start = self.chunk().len();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'")?;
         self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after expression")
+        self.consume(TokenType::RightParen, "Expect ')' after condition")?;
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse { offset: 0 })?;
+        self.emit_byte(OpCode::Pop)?;
+        self.begin_loop(label, loop_start);
+        self.statement()?;
+        self.emit_loop(loop_start)?;
+
+        self.chunk().patch_jump(exit_jump)?;
+        self.emit_byte(OpCode::Pop)?;
+        self.end_loop()
     }
 
-    fn unary(&mut self, token_type: TokenType) -> Result<()> {
-        self.parse_with_precendece(Precedence::Unary.to_number())?;
+    /// Compiles a C-style for. The increment clause is compiled before the body, so the
+    /// body jumps back to it and the increment then loops back to the condition.
+    fn for_statement(&mut self, label: Option<String>) -> Result<()> {
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'")?;
 
-        match token_type {
-            TokenType::Minus => self.emit_byte(OpCode::Negate),
-            _ => Ok(()),
+        if self.matches(TokenType::Semicolon)? {
+            // No initializer
+        } else if self.matches(TokenType::Var)? {
+            self.var_declaration()?;
+        } else {
+            self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after expression")?;
+            self.emit_byte(OpCode::Pop)?;
         }
-    }
 
-    fn parse_prefix_for_type(&mut self, token_type: TokenType) -> Result<()> {
-        match token_type {
-            TokenType::LeftParen => self.grouping(),
-            TokenType::Minus => self.unary(token_type),
-            TokenType::Number => self.number(),
-            TokenType::Nil => self.literal(token_type),
-            TokenType::True => self.literal(token_type),
-            TokenType::False => self.literal(token_type),
-            unreq_token_type => ReloxError::new_fatal_error(format!(
-                "Prefix unimplemented for {:?}",
-                unreq_token_type
-            )),
+        let mut loop_start = self.chunk().len();
+        let mut exit_jump = None;
+
+        if !self.matches(TokenType::Semicolon)? {
+            self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after loop condition")?;
+
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse { offset: 0 })?);
+            self.emit_byte(OpCode::Pop)?;
         }
-    }
 
-    fn parse_infix_for_type(&mut self, token_type: TokenType) -> Result<()> {
-        match token_type {
-            TokenType::Slash => self.binary(token_type),
-            TokenType::Minus => self.binary(token_type),
-            TokenType::Plus => self.binary(token_type),
-            TokenType::Star => self.binary(token_type),
-            unreq_token_type => ReloxError::new_fatal_error(format!(
-                "Infix unimplemented for {:?}",
-                unreq_token_type
-            )),
+        if !self.matches(TokenType::RightParen)? {
+            let body_jump = self.emit_jump(OpCode::Jump { offset: 0 })?;
+            let increment_start = self.chunk().len();
+
+            self.expression()?;
+            self.emit_byte(OpCode::Pop)?;
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses")?;
+
+            self.emit_loop(loop_start)?;
+            loop_start = increment_start;
+            self.chunk().patch_jump(body_jump)?;
         }
+
+        self.begin_loop(label, loop_start);
+        self.statement()?;
+        self.emit_loop(loop_start)?;
+
+        if let Some(exit_jump) = exit_jump {
+            self.chunk().patch_jump(exit_jump)?;
+            self.emit_byte(OpCode::Pop)?;
+        }
+
+        self.end_loop()?;
+        self.end_scope()
     }
 
-    // == ByteCode Handling ==
-    fn emit_constant(&mut self) -> Result<()> {
-        let prev_token = self.prev_token()?;
-        match prev_token.literal {
-            Some(Literal::Double(value)) => {
-                let token_line = prev_token.line as u16;
-                self.chunk.add_constant(Value::Number(value), token_line)?;
-                Ok(())
-            }
-            _ => ReloxError::new_compile_error(
-                prev_token.line,
-                "Error on compilation".to_string(),
-                None,
-                Fatal,
-            ),
+    fn labeled_statement(&mut self) -> Result<()> {
+        self.advance()?;
+        let label = self.prev_token()?.lexeme.clone();
+        self.advance()?;
+
+        if self.matches(TokenType::While)? {
+            self.while_statement(Some(label))
+        } else if self.matches(TokenType::For)? {
+            self.for_statement(Some(label))
+        } else {
+            let token = self.current_token()?.clone();
+            self.handle_error(token, "Expect a loop after a label");
+            // The parser isn't lost, what follows the label is still a statement
+            self.panic_mode = false;
+            self.statement()
         }
     }
 
-    fn emit_byte(&mut self, bytecode: OpCode) -> Result<()> {
-        let prev_token = self.prev_token()?;
-        let token_line = prev_token.line as u16;
-        self.chunk.write_bytecode(bytecode, token_line);
+    fn break_statement(&mut self) -> Result<()> {
+        if let Some(index) = self.jump_target("break")? {
+            self.inline_finally_blocks(index + 1)?;
+            let scope_depth = self.current.loops[index].scope_depth;
+            self.discard_locals(scope_depth)?;
+            let jump = self.emit_jump(OpCode::Jump { offset: 0 })?;
+            self.current.loops[index].break_jumps.push(jump);
+        }
         Ok(())
     }
 
-    fn emit_return(&mut self) -> Result<()> {
-        self.emit_byte(OpCode::Return)?;
+    fn continue_statement(&mut self) -> Result<()> {
+        if let Some(index) = self.jump_target("continue")? {
+            self.inline_finally_blocks(index + 1)?;
+            let scope_depth = self.current.loops[index].scope_depth;
+            self.discard_locals(scope_depth)?;
+            let continue_target = self.current.loops[index].continue_target;
+            self.emit_loop(continue_target)?;
+        }
         Ok(())
     }
 
-    // ==  Utility Functions ==
-    fn handle_error(&mut self, token: Token, message: &str) {
-        if self.panic_mode {
-            self.cursor += 1;
-            return;
-        }
-        self.panic_mode = true;
+    /// Parses the optional label and the ';' after a `break` or `continue`, returning the index
+    /// of the loop it applies to
+    fn jump_target(&mut self, keyword: &str) -> Result<Option<usize>> {
+        let keyword_token = self.prev_token()?.clone();
+        let label = match self.matches(TokenType::Identifier)? {
+            true => Some(self.prev_token()?.clone()),
+            false => None,
+        };
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expect ';' after '{}'", keyword),
+        )?;
 
-        match token.token_type {
-            TokenType::Eof => println!("[line {}] Error at end: {}", token.line, message),
-            _ => println!(
-                "[line {}] Error at {}: {}",
-                token.line, token.lexeme, message
+        let loops = &self.current.loops;
+        let index = match &label {
+            Some(label) => loops
+                .iter()
+                .rposition(|state| state.label.as_deref() == Some(label.lexeme.as_str())),
+            None => loops.len().checked_sub(1),
+        };
+
+        match (index, label) {
+            (Some(index), _) => return Ok(Some(index)),
+            (None, _) if loops.is_empty() => self.handle_error(
+                keyword_token,
+                &format!("Can't use '{}' outside of a loop", keyword),
             ),
+            (None, Some(label)) => {
+                let message = format!("No loop labeled '{}'", label.lexeme);
+                self.handle_error(label, &message)
+            }
+            (None, None) => {}
         }
-        self.had_error = true;
+        Ok(None)
     }
 
-    fn current_token(&self) -> Result<&Token> {
-        if let Some(token) = self.tokens.get(self.cursor) {
-            Ok(token)
-        } else {
-            ReloxError::new_fatal_error("Parser tried to fetch an unexistent token".to_string())
-        }
+    fn begin_loop(&mut self, label: Option<String>, continue_target: usize) {
+        let scope_depth = self.current.scope_depth;
+        self.current.loops.push(LoopState {
+            label,
+            continue_target,
+            scope_depth,
+            break_jumps: Vec::new(),
+        });
     }
 
-    fn current_token_type(&self) -> Result<TokenType> {
-        Ok(self.current_token()?.token_type)
+    fn end_loop(&mut self) -> Result<()> {
+        if let Some(state) = self.current.loops.pop() {
+            for jump in state.break_jumps {
+                self.chunk().patch_jump(jump)?;
+            }
+        }
+        Ok(())
     }
 
-    fn prev_token(&self) -> Result<&Token> {
-        self.prev_token_for(self.cursor)
-    }
+    fn return_statement(&mut self) -> Result<()> {
+        if let FunctionType::Script | FunctionType::Module = self.current.function_type {
+            let token = self.prev_token()?.clone();
+            self.handle_error(token, "Can't return from top-level code");
+        }
 
-    fn prev_token_type(&self) -> Result<TokenType> {
-        Ok(self.prev_token()?.token_type)
+        if self.matches(TokenType::Semicolon)? {
+            self.inline_finally_blocks(0)?;
+            return self.emit_return();
+        }
+
+        if self.current.function_type == FunctionType::Initializer {
+            let token = self.prev_token()?.clone();
+            self.handle_error(token, "Can't return a value from an initializer");
+        }
+
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after return value")?;
+
+        if !self.current.finally_blocks.is_empty() {
+            // The returned value sits on the stack while the finally blocks run
+            let token = self.prev_token()?.clone();
+            self.begin_scope();
+            self.add_local(Self::synthetic_token("", &token));
+            self.mark_initialized();
+            self.inline_finally_blocks(0)?;
+            self.forget_scope();
+        }
+        self.emit_byte(OpCode::Return)
     }
 
-    fn prev_token_for(&self, cursor: usize) -> Result<&Token> {
-        if let Some(token) = self.tokens.get(cursor - 1) {
-            Ok(token)
+    /// Compiles `try` with a `catch`, a `finally` or both. The catch is the handler of the
+    /// try block. The finally is compiled twice: once for the normal exit and once as the
+    /// handler of the try block, or of the catch block if there's one, rethrowing afterwards.
+    fn try_statement(&mut self) -> Result<()> {
+        let stack_depth = self.current.locals.len();
+        let try_depth = self.current.try_depth;
+        let finally_cursor = self.find_finally();
+        if let Some(cursor) = finally_cursor {
+            let loop_depth = self.current.loops.len();
+            self.current.finally_blocks.push(FinallyBlock {
+                cursor,
+                loop_depth,
+                try_depth,
+            });
+        }
+        self.current.try_depth += 1;
+
+        let try_start = self.chunk().len();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'")?;
+        self.begin_scope();
+        self.block()?;
+        self.end_scope()?;
+        let mut protected = (try_start, self.chunk().len());
+        let mut exit_jumps = vec![self.emit_jump(OpCode::Jump { offset: 0 })?];
+
+        let has_catch = self.matches(TokenType::Catch)?;
+        if has_catch {
+            let catch_start = self.chunk().len();
+            self.add_handlers(protected, catch_start, stack_depth, try_depth);
+
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'")?;
+            self.begin_scope();
+            self.consume(TokenType::Identifier, "Expect exception variable name")?;
+            self.declare_local()?;
+            self.mark_initialized();
+            self.consume(TokenType::RightParen, "Expect ')' after exception variable")?;
+            self.consume(TokenType::LeftBrace, "Expect '{' before catch body")?;
+            self.block()?;
+            self.end_scope()?;
+
+            protected = (catch_start, self.chunk().len());
+            exit_jumps.push(self.emit_jump(OpCode::Jump { offset: 0 })?);
+        }
+
+        if finally_cursor.is_some() {
+            self.current.finally_blocks.pop();
+        }
+        self.current.try_depth = try_depth;
+
+        if self.matches(TokenType::Finally)? {
+            let finally_start = self.chunk().len();
+            self.add_handlers(protected, finally_start, stack_depth, try_depth);
+
+            // The exception stays on the stack while the finally block runs
+            let body_cursor = self.cursor;
+            let token = self.prev_token()?.clone();
+            self.begin_scope();
+            self.add_local(Self::synthetic_token("", &token));
+            self.mark_initialized();
+            self.finally_block()?;
+            self.emit_byte(OpCode::Throw)?;
+            self.forget_scope();
+
+            for jump in exit_jumps {
+                self.chunk().patch_jump(jump)?;
+            }
+            self.cursor = body_cursor;
+            self.finally_block()
         } else {
-            ReloxError::new_fatal_error("Parser tried to fetch an unexistent token".to_string())
+            if !has_catch {
+                let token = self.current_token()?.clone();
+                self.handle_error(token, "Expect 'catch' or 'finally' after try block");
+            }
+            for jump in exit_jumps {
+                self.chunk().patch_jump(jump)?;
+            }
+            Ok(())
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::bytecode::vm::Vm;
-    use crate::scanner::Scanner;
+    /// Adds the handlers of the `try` at `try_depth` for the code in `protected`, leaving out
+    /// the finally blocks inlined in it that run outside of the `try`
+    fn add_handlers(
+        &mut self,
+        protected: (usize, usize),
+        target: usize,
+        stack_depth: usize,
+        try_depth: usize,
+    ) {
+        // Blocks are recorded once compiled, so the ones nested in a block come before it
+        let mut inlined_blocks = self.current.inlined_finally_blocks.clone();
+        inlined_blocks.sort_by_key(|inlined| (inlined.start, std::cmp::Reverse(inlined.end)));
 
-    use super::*;
+        let (mut start, end) = protected;
+        let mut pieces = Vec::new();
+        for inlined in inlined_blocks {
+            if inlined.try_depth <= try_depth && start <= inlined.start && inlined.end <= end {
+                pieces.push((start, inlined.start));
+                start = start.max(inlined.end);
+            }
+        }
+        pieces.push((start, end));
 
-    #[test]
-    fn test_simple_addition() {
-        let tokens = Scanner::run_with("1 + 2".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        // dbg!(&chunk);
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Number(3.0));
+        for (start, end) in pieces {
+            if start < end {
+                self.chunk().add_handler(ExceptionHandler {
+                    start,
+                    end,
+                    target,
+                    stack_depth,
+                });
+            }
+        }
     }
 
-    #[test]
-    fn test_simple_substraction() {
-        let tokens = Scanner::run_with("3 - 2".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Number(1.0));
+    fn finally_block(&mut self) -> Result<()> {
+        self.consume(TokenType::LeftBrace, "Expect '{' before finally body")?;
+        self.begin_scope();
+        self.block()?;
+        self.end_scope()
     }
 
-    #[test]
-    fn test_addition_with_mult() {
-        let tokens = Scanner::run_with("1 + 2 * 3".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Number(7.0));
-    }
+    /// Looks ahead for the `finally` of the `try` being compiled, skipping over the try and
+    /// catch blocks. Returns the cursor of the finally body.
+    fn find_finally(&self) -> Option<usize> {
+        let mut cursor = self.skip_delimited(self.cursor, TokenType::LeftBrace)?;
 
-    #[test]
-    fn test_addition_with_mult2() {
-        let tokens = Scanner::run_with("1 * 3 + 2".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Number(5.0));
+        if self.tokens.get(cursor)?.token_type == TokenType::Catch {
+            cursor = self.skip_delimited(cursor + 1, TokenType::LeftParen)?;
+            cursor = self.skip_delimited(cursor, TokenType::LeftBrace)?;
+        }
+
+        match self.tokens.get(cursor)?.token_type {
+            TokenType::Finally => Some(cursor + 1),
+            _ => None,
+        }
     }
 
-    #[test]
+    /// The cursor right after the group opened by the `open` token at `cursor`
+    fn skip_delimited(&self, mut cursor: usize, open: TokenType) -> Option<usize> {
+        let close = match open {
+            TokenType::LeftParen => TokenType::RightParen,
+            _ => TokenType::RightBrace,
+        };
+
+        if self.tokens.get(cursor)?.token_type != open {
+            return None;
+        }
+
+        let mut depth = 0;
+        loop {
+            let token_type = self.tokens.get(cursor)?.token_type;
+            cursor += 1;
+            if token_type == open {
+                depth += 1;
+            } else if token_type == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(cursor);
+                }
+            }
+        }
+    }
+
+    /// Compiles the finally blocks a jump leaves, from the innermost one out to the first one
+    /// inside `loop_depth` loops
+    fn inline_finally_blocks(&mut self, loop_depth: usize) -> Result<()> {
+        let cursor = self.cursor;
+        let mut index = self.current.finally_blocks.len();
+
+        while index > 0 && self.current.finally_blocks[index - 1].loop_depth >= loop_depth {
+            index -= 1;
+            // The block being inlined and the ones inside it don't protect its own code
+            let inner = self.current.finally_blocks.split_off(index);
+            let try_depth = inner[0].try_depth;
+            let start = self.chunk().len();
+            self.cursor = inner[0].cursor;
+            let enclosing_try_depth = std::mem::replace(&mut self.current.try_depth, try_depth);
+            let result = self.finally_block();
+            self.current.try_depth = enclosing_try_depth;
+            self.current.finally_blocks.extend(inner);
+            result?;
+
+            let end = self.chunk().len();
+            self.current.inlined_finally_blocks.push(InlinedFinally {
+                start,
+                end,
+                try_depth,
+            });
+        }
+
+        self.cursor = cursor;
+        Ok(())
+    }
+
+    /// Binds the module at the path to a global named after the file, `import "lib/math.lox";`
+    /// defines `math`. The path is relative to the importing file.
+    fn import_statement(&mut self) -> Result<()> {
+        let import_token = self.prev_token()?.clone();
+        self.consume(TokenType::String, "Expect module path after 'import'")?;
+        let path_token = self.prev_token()?.clone();
+        self.consume(TokenType::Semicolon, "Expect ';' after module path")?;
+
+        let is_top_level = matches!(
+            self.current.function_type,
+            FunctionType::Script | FunctionType::Module
+        ) && self.current.scope_depth == 0;
+        if !is_top_level {
+            self.handle_error(import_token, "Can only import at the top level of a file");
+            return Ok(());
+        }
+
+        let import_path = match &path_token.literal {
+            Some(Literal::String(import_path)) => import_path.clone(),
+            _ => return Ok(()),
+        };
+        let path = match &self.path {
+            Some(importer) => importer.with_file_name(&import_path),
+            None => PathBuf::from(&import_path),
+        };
+
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string());
+        let name = match name {
+            Some(name) if is_identifier(&name) => name,
+            _ => {
+                let message = format!("Module '{}' isn't named like a variable", import_path);
+                self.handle_error(path_token, &message);
+                return Ok(());
+            }
+        };
+
+        if let Some(function) = self.compile_module(&path_token, path)? {
+            let constant_offset = self.chunk().make_constant(Value::Function(function))?;
+            self.emit_byte(OpCode::Import { constant_offset })?;
+            let global = self.identifier_constant(&name)?;
+            self.emit_byte(OpCode::DefineGlobal {
+                constant_offset: global,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Compiles the module at `path` into a function, unless it was compiled already. The
+    /// function is named after the canonical path, which identifies the module in the vm.
+    fn compile_module(&mut self, token: &Token, path: PathBuf) -> Result<Option<Rc<ObjFunction>>> {
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => {
+                let message = format!("Can't find module '{}'", path.display());
+                self.handle_error(token.clone(), &message);
+                return Ok(None);
+            }
+        };
+
+        let importing = &self.modules.importing;
+        if let Some(start) = importing.iter().position(|(file, _)| *file == canonical) {
+            let mut cycle: Vec<&str> = importing[start..]
+                .iter()
+                .map(|(_, shown)| shown.as_str())
+                .collect();
+            let shown = path.display().to_string();
+            cycle.push(&shown);
+            let message = format!("Circular import: {}", cycle.join(" -> "));
+            self.handle_error(token.clone(), &message);
+            return Ok(None);
+        }
+
+        if let Some(function) = self.modules.compiled.get(&canonical) {
+            return Ok(Some(Rc::clone(function)));
+        }
+
+        let source = match fs::read_to_string(&canonical) {
+            Ok(source) => source,
+            Err(error) => {
+                let message = format!("Can't read module '{}': {}", path.display(), error);
+                self.handle_error(token.clone(), &message);
+                return Ok(None);
+            }
+        };
+        let tokens = Scanner::run_with(source)?;
+        let name = self.interner.intern(&canonical.display().to_string());
+
+        let mut compiler = Compiler::new(tokens, &mut *self.interner);
+        compiler.current = FunctionState::new(ObjFunction::new(Some(name)), FunctionType::Module);
+        compiler.path = Some(path.clone());
+        compiler.modules = std::mem::take(&mut self.modules);
+        compiler
+            .modules
+            .importing
+            .push((canonical.clone(), path.display().to_string()));
+
+        let result = compiler.parse().and_then(|_| compiler.emit_return());
+        self.modules = std::mem::take(&mut compiler.modules);
+        self.modules.importing.pop();
+        result?;
+
+        let function = Rc::new(compiler.current.function);
+        self.modules
+            .compiled
+            .insert(canonical, Rc::clone(&function));
+        Ok(Some(function))
+    }
+
+    fn throw_statement(&mut self) -> Result<()> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value")?;
+        self.emit_byte(OpCode::Throw)
+    }
+
+    fn print_statement(&mut self) -> Result<()> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value")?;
+        self.emit_byte(OpCode::Print)
+    }
+
+    /// A top level expression without a ';' right before the end of the input is left on the
+    /// stack and becomes the result of the chunk, which is what the REPL prints. The body of an
+    /// `if` or a loop isn't a declaration of its own, so it still needs the ';'.
+    fn expression_statement(&mut self) -> Result<()> {
+        let is_declaration = self.cursor == self.declaration_start;
+        self.expression()?;
+
+        if is_declaration && self.current.scope_depth == 0 && self.check(TokenType::Eof)? {
+            self.has_trailing_expression = true;
+            return Ok(());
+        }
+
+        self.consume(TokenType::Semicolon, "Expect ';' after expression")?;
+        self.emit_byte(OpCode::Pop)
+    }
+
+    fn synchronize(&mut self) -> Result<()> {
+        self.panic_mode = false;
+
+        while !self.check(TokenType::Eof)? {
+            if self.prev_token_type()? == TokenType::Semicolon {
+                return Ok(());
+            }
+
+            match self.current_token_type()? {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Try
+                | TokenType::Throw
+                | TokenType::Import => return Ok(()),
+                _ => self.advance()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        loop {
+            let token = self.current_token()?;
+
+            if token.token_type == TokenType::ErrorToken {
+                let token_cl = token.clone();
+                self.handle_error(token_cl, "");
+            } else {
+                break;
+            }
+        }
+
+        self.cursor += 1;
+        Ok(())
+    }
+
+    fn check(&self, token_type: TokenType) -> Result<bool> {
+        Ok(self.current_token_type()? == token_type)
+    }
+
+    fn matches(&mut self, token_type: TokenType) -> Result<bool> {
+        if !self.check(token_type)? {
+            return Ok(false);
+        }
+        self.advance()?;
+        Ok(true)
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<()> {
+        let token = self.current_token()?;
+
+        if token.token_type == token_type {
+            self.advance()?;
+        } else {
+            let token = token.clone();
+            self.handle_error(token, message)
+        }
+        Ok(())
+    }
+
+    /// `**` is right associative and its right operand can be negated, so its operand is
+    /// parsed at the unary precedence: `2 ** -3 ** 2` is `2 ** (-(3 ** 2))`.
+    fn binary(&mut self, token_type: TokenType) -> Result<()> {
+        let operand_precedence = match token_type {
+            TokenType::StarStar => Precedence::Unary.to_number(),
+            _ => Precedence::new(token_type).to_number() + 1,
+        };
+        self.parse_with_precendece(operand_precedence)?;
+
+        match token_type {
+            TokenType::Plus => self.emit_byte(OpCode::Add),
+            TokenType::Minus => self.emit_byte(OpCode::Substract),
+            TokenType::Star => self.emit_byte(OpCode::Multiply),
+            TokenType::Slash => self.emit_byte(OpCode::Divide),
+            TokenType::Percent => self.emit_byte(OpCode::Modulo),
+            TokenType::StarStar => self.emit_byte(OpCode::Power),
+            TokenType::Ampersand => self.emit_byte(OpCode::BitAnd),
+            TokenType::Pipe => self.emit_byte(OpCode::BitOr),
+            TokenType::Caret => self.emit_byte(OpCode::BitXor),
+            TokenType::LessLess => self.emit_byte(OpCode::ShiftLeft),
+            TokenType::GreaterGreater => self.emit_byte(OpCode::ShiftRight),
+            TokenType::EqualEqual => self.emit_byte(OpCode::Equal),
+            TokenType::BangEqual => {
+                self.emit_byte(OpCode::Equal)?;
+                self.emit_byte(OpCode::Not)
+            }
+            TokenType::Greater => self.emit_byte(OpCode::Greater),
+            TokenType::GreaterEqual => {
+                self.emit_byte(OpCode::Less)?;
+                self.emit_byte(OpCode::Not)
+            }
+            TokenType::Less => self.emit_byte(OpCode::Less),
+            TokenType::LessEqual => {
+                self.emit_byte(OpCode::Greater)?;
+                self.emit_byte(OpCode::Not)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `and` short-circuits: when the left operand is falsey it's left on the stack as the
+    /// result and the right operand is skipped.
+    fn and(&mut self) -> Result<()> {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse { offset: 0 })?;
+
+        self.emit_byte(OpCode::Pop)?;
+        self.parse_with_precendece(Precedence::And.to_number())?;
+
+        self.chunk().patch_jump(end_jump)
+    }
+
+    /// `or` short-circuits: when the left operand is truthy it's left on the stack as the
+    /// result and the right operand is skipped.
+    fn or(&mut self) -> Result<()> {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse { offset: 0 })?;
+        let end_jump = self.emit_jump(OpCode::Jump { offset: 0 })?;
+
+        self.chunk().patch_jump(else_jump)?;
+        self.emit_byte(OpCode::Pop)?;
+        self.parse_with_precendece(Precedence::Or.to_number())?;
+
+        self.chunk().patch_jump(end_jump)
+    }
+
+    fn call(&mut self) -> Result<()> {
+        let arg_count = self.argument_list()?;
+        self.emit_byte(OpCode::Call { arg_count })
+    }
+
+    fn argument_list(&mut self) -> Result<u8> {
+        let mut arg_count: u8 = 0;
+
+        if !self.check(TokenType::RightParen)? {
+            loop {
+                self.expression()?;
+
+                if arg_count == u8::MAX {
+                    let token = self.prev_token()?.clone();
+                    self.handle_error(token, "Can't have more than 255 arguments");
+                } else {
+                    arg_count += 1;
+                }
+
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "Expect ')' after arguments")?;
+        Ok(arg_count)
+    }
+
+    fn parse_with_precendece(&mut self, precedence: u8) -> Result<()> {
+        self.advance()?;
+
+        // dbg!(self.prev_token()?, self.current_token()?, self.cursor);
+
+        let can_assign = precedence <= Precedence::Assignment.to_number();
+        self.parse_prefix_for_type(self.prev_token_type()?, can_assign)?;
+
+        while precedence <= Precedence::new(self.current_token_type()?).to_number() {
+            self.advance()?;
+            self.parse_infix_for_type(self.prev_token_type()?, can_assign)?
+        }
+
+        if can_assign && self.matches(TokenType::Equal)? {
+            let token = self.prev_token()?.clone();
+            self.handle_error(token, "Invalid assignment target");
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<()> {
+        self.parse_with_precendece(Precedence::Assignment.to_number())
+    }
+
+    fn number(&mut self) -> Result<()> {
+        self.emit_constant()
+    }
+
+    fn string(&mut self) -> Result<()> {
+        self.emit_constant()
+    }
+
+    /// `"a ${b} c"` is scanned as the `a ` segment, the tokens of `b` and the ` c` string. It's
+    /// compiled as `"a " + b + " c"` with `b` converted to a string, dropping empty trailing
+    /// segments.
+    fn interpolation(&mut self) -> Result<()> {
+        self.emit_constant()?;
+
+        loop {
+            self.expression()?;
+            self.emit_byte(OpCode::ToString)?;
+            self.emit_byte(OpCode::Add)?;
+
+            if !self.matches(TokenType::Interpolation)? {
+                self.consume(
+                    TokenType::String,
+                    "Expect '}' after interpolated expression",
+                )?;
+            }
+
+            if !matches!(&self.prev_token()?.literal, Some(Literal::String(segment)) if segment.is_empty())
+            {
+                self.emit_constant()?;
+                self.emit_byte(OpCode::Add)?;
+            }
+
+            if self.prev_token()?.token_type == TokenType::String {
+                return Ok(());
+            }
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) -> Result<()> {
+        let name_token = self.prev_token()?.clone();
+        self.named_variable(&name_token, can_assign)
+    }
+
+    fn this(&mut self) -> Result<()> {
+        if self.classes.is_empty() {
+            let token = self.prev_token()?.clone();
+            self.handle_error(token, "Can't use 'this' outside of a class");
+            return Ok(());
+        }
+
+        // `this` is a local living in slot 0 of methods, captured like any other variable
+        self.variable(false)
+    }
+
+    /// `super.method` binds the superclass method to `this`. It's resolved statically through
+    /// the `super` local of the class declaration.
+    fn super_(&mut self) -> Result<()> {
+        let super_token = self.prev_token()?.clone();
+
+        match self.classes.last() {
+            None => self.handle_error(super_token.clone(), "Can't use 'super' outside of a class"),
+            Some(ClassState {
+                has_superclass: false,
+            }) => self.handle_error(
+                super_token.clone(),
+                "Can't use 'super' in a class with no superclass",
+            ),
+            Some(_) => {}
+        }
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'")?;
+        self.consume(TokenType::Identifier, "Expect superclass method name")?;
+        let name = self.prev_token()?.lexeme.clone();
+        let constant_offset = self.identifier_constant(&name)?;
+
+        self.named_variable(&Self::synthetic_token("this", &super_token), false)?;
+        self.named_variable(&super_token, false)?;
+        self.emit_byte(OpCode::GetSuper { constant_offset })
+    }
+
+    fn dot(&mut self, can_assign: bool) -> Result<()> {
+        self.consume(TokenType::Identifier, "Expect property name after '.'")?;
+        let name = self.prev_token()?.lexeme.clone();
+        let constant_offset = self.identifier_constant(&name)?;
+
+        if can_assign && self.matches(TokenType::Equal)? {
+            self.expression()?;
+            self.emit_byte(OpCode::SetProperty { constant_offset })
+        } else {
+            self.emit_byte(OpCode::GetProperty { constant_offset })
+        }
+    }
+
+    fn list(&mut self) -> Result<()> {
+        let mut item_count: u8 = 0;
+
+        if !self.check(TokenType::RightBracket)? {
+            loop {
+                self.expression()?;
+
+                if item_count == u8::MAX {
+                    let token = self.prev_token()?.clone();
+                    self.handle_error(token, "Can't have more than 255 items in a list literal");
+                } else {
+                    item_count += 1;
+                }
+
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list items")?;
+        self.emit_byte(OpCode::BuildList { item_count })
+    }
+
+    fn map(&mut self) -> Result<()> {
+        let mut entry_count: u8 = 0;
+
+        if !self.check(TokenType::RightBrace)? {
+            loop {
+                self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key")?;
+                self.expression()?;
+
+                if entry_count == u8::MAX {
+                    let token = self.prev_token()?.clone();
+                    self.handle_error(token, "Can't have more than 255 entries in a map literal");
+                } else {
+                    entry_count += 1;
+                }
+
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries")?;
+        self.emit_byte(OpCode::BuildMap { entry_count })
+    }
+
+    fn subscript(&mut self, can_assign: bool) -> Result<()> {
+        self.expression()?;
+        self.consume(TokenType::RightBracket, "Expect ']' after index")?;
+
+        if can_assign && self.matches(TokenType::Equal)? {
+            self.expression()?;
+            self.emit_byte(OpCode::SetIndex)
+        } else {
+            self.emit_byte(OpCode::GetIndex)
+        }
+    }
+
+    fn named_variable(&mut self, name_token: &Token, can_assign: bool) -> Result<()> {
+        let (get_op, set_op) = if let Some(slot) = self.resolve_local(0, name_token)? {
+            (OpCode::GetLocal { slot }, OpCode::SetLocal { slot })
+        } else if let Some(slot) = self.resolve_upvalue(0, name_token)? {
+            (OpCode::GetUpvalue { slot }, OpCode::SetUpvalue { slot })
+        } else {
+            let constant_offset = self.identifier_constant(&name_token.lexeme)?;
+            (
+                OpCode::GetGlobal { constant_offset },
+                OpCode::SetGlobal { constant_offset },
+            )
+        };
+
+        if can_assign && self.matches(TokenType::Equal)? {
+            self.expression()?;
+            self.emit_byte(set_op)
+        } else {
+            self.emit_byte(get_op)
+        }
+    }
+
+    /// Parses a variable name. Globals get their interned name added to the constant pool and its
+    /// offset returned, locals are declared in the current scope and return `None`.
+    fn parse_variable(&mut self, message: &str) -> Result<Option<u8>> {
+        self.consume(TokenType::Identifier, message)?;
+
+        if self.current.scope_depth > 0 {
+            self.declare_local()?;
+            return Ok(None);
+        }
+
+        let name = self.prev_token()?.lexeme.clone();
+        Ok(Some(self.identifier_constant(&name)?))
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> Result<u8> {
+        let name = self.interner.intern(name);
+        self.chunk().add_identifier(name)
+    }
+
+    fn define_variable(&mut self, global: Option<u8>) -> Result<()> {
+        match global {
+            Some(constant_offset) => self.emit_byte(OpCode::DefineGlobal { constant_offset }),
+            None => {
+                self.mark_initialized();
+                Ok(())
+            }
+        }
+    }
+
+    // == Scopes and Locals ==
+    fn begin_scope(&mut self) {
+        self.current.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) -> Result<()> {
+        self.current.scope_depth -= 1;
+
+        while let Some(local) = self.current.locals.last() {
+            match local.depth {
+                Some(depth) if depth <= self.current.scope_depth => break,
+                _ => {
+                    if local.is_captured {
+                        self.emit_byte(OpCode::CloseUpvalue)?;
+                    } else {
+                        self.emit_byte(OpCode::Pop)?;
+                    }
+                    self.current.locals.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the innermost scope without emitting pops, for code that never reaches its end
+    fn forget_scope(&mut self) {
+        self.current.scope_depth -= 1;
+
+        while let Some(local) = self.current.locals.last() {
+            match local.depth {
+                Some(depth) if depth <= self.current.scope_depth => break,
+                _ => self.current.locals.pop(),
+            };
+        }
+    }
+
+    /// Emits the pops for the locals deeper than `scope_depth` without forgetting them, for
+    /// jumps that leave their scope early
+    fn discard_locals(&mut self, scope_depth: usize) -> Result<()> {
+        let discarded: Vec<bool> = self
+            .current
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth > scope_depth))
+            .map(|local| local.is_captured)
+            .collect();
+
+        for is_captured in discarded {
+            match is_captured {
+                true => self.emit_byte(OpCode::CloseUpvalue)?,
+                false => self.emit_byte(OpCode::Pop)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_local(&mut self) -> Result<()> {
+        let name_token = self.prev_token()?.clone();
+
+        let already_declared = self
+            .current
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| match local.depth {
+                Some(depth) => depth >= self.current.scope_depth,
+                None => true,
+            })
+            .any(|local| local.name == name_token.lexeme);
+
+        if already_declared {
+            self.handle_error(
+                name_token,
+                "Already a variable with this name in this scope",
+            );
+            return Ok(());
+        }
+
+        self.add_local(name_token);
+        Ok(())
+    }
+
+    fn add_local(&mut self, name_token: Token) {
+        if self.current.locals.len() >= LOCALS_MAX {
+            self.handle_error(name_token, "Too many local variables in scope");
+            return;
+        }
+
+        self.current.locals.push(Local {
+            name: name_token.lexeme,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    fn mark_initialized(&mut self) {
+        if self.current.scope_depth == 0 {
+            return;
+        }
+
+        if let Some(local) = self.current.locals.last_mut() {
+            local.depth = Some(self.current.scope_depth);
+        }
+    }
+
+    /// The function being compiled `depth` levels up from the current one
+    fn state(&self, depth: usize) -> &FunctionState {
+        match depth {
+            0 => &self.current,
+            _ => &self.enclosing[self.enclosing.len() - depth],
+        }
+    }
+
+    fn state_mut(&mut self, depth: usize) -> &mut FunctionState {
+        match depth {
+            0 => &mut self.current,
+            _ => {
+                let index = self.enclosing.len() - depth;
+                &mut self.enclosing[index]
+            }
+        }
+    }
+
+    /// Returns the stack slot of the innermost local named like the token in the function
+    /// `depth` levels up, or `None` if it isn't a local there.
+    fn resolve_local(&mut self, depth: usize, name_token: &Token) -> Result<Option<u8>> {
+        let found = self
+            .state(depth)
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name_token.lexeme)
+            .map(|(slot, local)| (slot, local.depth));
+
+        match found {
+            Some((_, None)) => {
+                self.handle_error(
+                    name_token.clone(),
+                    "Can't read local variable in its own initializer",
+                );
+                Ok(None)
+            }
+            Some((slot, Some(_))) => Ok(Some(slot as u8)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the upvalue index of a variable captured by the function `depth` levels up,
+    /// looking for it in the enclosing functions. `None` means it must be a global.
+    fn resolve_upvalue(&mut self, depth: usize, name_token: &Token) -> Result<Option<u8>> {
+        if depth >= self.enclosing.len() {
+            return Ok(None);
+        }
+
+        if let Some(slot) = self.resolve_local(depth + 1, name_token)? {
+            self.state_mut(depth + 1).locals[slot as usize].is_captured = true;
+            return self.add_upvalue(depth, slot, true, name_token);
+        }
+
+        match self.resolve_upvalue(depth + 1, name_token)? {
+            Some(index) => self.add_upvalue(depth, index, false, name_token),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        depth: usize,
+        index: u8,
+        is_local: bool,
+        name_token: &Token,
+    ) -> Result<Option<u8>> {
+        let descriptor = UpvalueDescriptor { index, is_local };
+        let upvalues = &self.state(depth).function.upvalues;
+
+        if let Some(existing) = upvalues.iter().position(|upvalue| *upvalue == descriptor) {
+            return Ok(Some(existing as u8));
+        }
+
+        if upvalues.len() >= UPVALUES_MAX {
+            self.handle_error(name_token.clone(), "Too many closure variables in function");
+            return Ok(None);
+        }
+
+        let upvalues = &mut self.state_mut(depth).function.upvalues;
+        upvalues.push(descriptor);
+        Ok(Some((upvalues.len() - 1) as u8))
+    }
+
+    fn literal(&mut self, token_type: TokenType) -> Result<()> {
+        match token_type {
+            TokenType::Nil => self.emit_byte(OpCode::Nil),
+            TokenType::True => self.emit_byte(OpCode::True),
+            TokenType::False => self.emit_byte(OpCode::False),
+            _ => Ok(()),
+        }
+    }
+
+    fn grouping(&mut self) -> Result<()> {
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after expression")
+    }
+
+    fn unary(&mut self, token_type: TokenType) -> Result<()> {
+        self.parse_with_precendece(Precedence::Unary.to_number())?;
+
+        match token_type {
+            TokenType::Minus => self.emit_byte(OpCode::Negate),
+            TokenType::Bang => self.emit_byte(OpCode::Not),
+            TokenType::Tilde => self.emit_byte(OpCode::BitNot),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_prefix_for_type(&mut self, token_type: TokenType, can_assign: bool) -> Result<()> {
+        match token_type {
+            TokenType::Identifier => self.variable(can_assign),
+            TokenType::LeftParen => self.grouping(),
+            TokenType::Minus => self.unary(token_type),
+            TokenType::Bang => self.unary(token_type),
+            TokenType::Tilde => self.unary(token_type),
+            TokenType::Number => self.number(),
+            TokenType::String => self.string(),
+            TokenType::Interpolation => self.interpolation(),
+            TokenType::Nil => self.literal(token_type),
+            TokenType::True => self.literal(token_type),
+            TokenType::False => self.literal(token_type),
+            TokenType::This => self.this(),
+            TokenType::Super => self.super_(),
+            TokenType::LeftBracket => self.list(),
+            TokenType::LeftBrace => self.map(),
+            unreq_token_type => ReloxError::new_fatal_error(format!(
+                "Prefix unimplemented for {:?}",
+                unreq_token_type
+            )),
+        }
+    }
+
+    fn parse_infix_for_type(&mut self, token_type: TokenType, can_assign: bool) -> Result<()> {
+        match token_type {
+            TokenType::Slash => self.binary(token_type),
+            TokenType::Minus => self.binary(token_type),
+            TokenType::Plus => self.binary(token_type),
+            TokenType::Star => self.binary(token_type),
+            TokenType::Percent => self.binary(token_type),
+            TokenType::StarStar => self.binary(token_type),
+            TokenType::Ampersand => self.binary(token_type),
+            TokenType::Pipe => self.binary(token_type),
+            TokenType::Caret => self.binary(token_type),
+            TokenType::LessLess => self.binary(token_type),
+            TokenType::GreaterGreater => self.binary(token_type),
+            TokenType::EqualEqual => self.binary(token_type),
+            TokenType::BangEqual => self.binary(token_type),
+            TokenType::Greater => self.binary(token_type),
+            TokenType::GreaterEqual => self.binary(token_type),
+            TokenType::Less => self.binary(token_type),
+            TokenType::LessEqual => self.binary(token_type),
+            TokenType::And => self.and(),
+            TokenType::Or => self.or(),
+            TokenType::LeftParen => self.call(),
+            TokenType::Dot => self.dot(can_assign),
+            TokenType::LeftBracket => self.subscript(can_assign),
+            unreq_token_type => ReloxError::new_fatal_error(format!(
+                "Infix unimplemented for {:?}",
+                unreq_token_type
+            )),
+        }
+    }
+
+    // == ByteCode Handling ==
+    fn emit_constant(&mut self) -> Result<()> {
+        let prev_token = self.prev_token()?;
+        match prev_token.literal {
+            Some(Literal::Integer(value)) => {
+                let token_line = prev_token.line as u16;
+                self.chunk()
+                    .add_constant(Value::Integer(value), token_line)?;
+                Ok(())
+            }
+            Some(Literal::Double(value)) => {
+                let token_line = prev_token.line as u16;
+                self.chunk().add_constant(Value::Float(value), token_line)?;
+                Ok(())
+            }
+            Some(Literal::String(ref chars)) => {
+                let token_line = prev_token.line as u16;
+                let chars = chars.clone();
+                let value = Value::String(self.interner.intern_owned(chars));
+                self.chunk().add_constant(value, token_line)?;
+                Ok(())
+            }
+            _ => ReloxError::new_compile_error(
+                prev_token.line,
+                "Error on compilation".to_string(),
+                None,
+                Fatal,
+            ),
+        }
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current.function.chunk
+    }
+
+    fn emit_byte(&mut self, bytecode: OpCode) -> Result<()> {
+        let prev_token = self.prev_token()?;
+        let token_line = prev_token.line as u16;
+        self.chunk().write_bytecode(bytecode, token_line);
+        Ok(())
+    }
+
+    /// Emits a jump with a placeholder offset and returns its index so it can be back-patched
+    fn emit_jump(&mut self, jump: OpCode) -> Result<usize> {
+        self.emit_byte(jump)?;
+        Ok(self.chunk().len() - 1)
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> Result<()> {
+        let token_line = self.prev_token()?.line as u16;
+        self.chunk().write_loop(loop_start, token_line)
+    }
+
+    fn emit_return(&mut self) -> Result<()> {
+        match self.current.function_type {
+            // Initializers always return the instance
+            FunctionType::Initializer => self.emit_byte(OpCode::GetLocal { slot: 0 })?,
+            FunctionType::Script if self.has_trailing_expression => {}
+            // Modules evaluate to themselves, which is what `import` binds
+            FunctionType::Module => self.emit_byte(OpCode::CurrentModule)?,
+            _ => self.emit_byte(OpCode::Nil)?,
+        }
+        self.emit_byte(OpCode::Return)?;
+        Ok(())
+    }
+
+    // ==  Utility Functions ==
+    fn handle_error(&mut self, token: Token, message: &str) {
+        if self.panic_mode {
+            self.cursor += 1;
+            return;
+        }
+        self.panic_mode = true;
+
+        let mut where_it_was = match token.token_type {
+            TokenType::Eof => "at end".to_string(),
+            _ => format!("at '{}'", token.lexeme),
+        };
+        if let Some(path) = &self.path {
+            where_it_was = format!("in {} {}", path.display(), where_it_was);
+        }
+        println!("[line {}] Error {}: {}", token.line, where_it_was, message);
+
+        if self.first_error.is_none() {
+            self.first_error = Some(FirstError {
+                line: token.line,
+                message: message.to_string(),
+                where_it_was: Some(where_it_was),
+            });
+        }
+        self.had_error = true;
+    }
+
+    /// A token for a variable the compiler introduces itself, like `this` and `super`
+    fn synthetic_token(lexeme: &str, at: &Token) -> Token {
+        Token::new(TokenType::Identifier, lexeme, None, at.line)
+    }
+
+    fn current_token(&self) -> Result<&Token> {
+        if let Some(token) = self.tokens.get(self.cursor) {
+            Ok(token)
+        } else {
+            ReloxError::new_fatal_error("Parser tried to fetch an unexistent token".to_string())
+        }
+    }
+
+    fn current_token_type(&self) -> Result<TokenType> {
+        Ok(self.current_token()?.token_type)
+    }
+
+    fn next_token_type(&self) -> Result<TokenType> {
+        match self.tokens.get(self.cursor + 1) {
+            Some(token) => Ok(token.token_type),
+            None => Ok(TokenType::Eof),
+        }
+    }
+
+    fn prev_token(&self) -> Result<&Token> {
+        self.prev_token_for(self.cursor)
+    }
+
+    fn prev_token_type(&self) -> Result<TokenType> {
+        Ok(self.prev_token()?.token_type)
+    }
+
+    fn prev_token_for(&self, cursor: usize) -> Result<&Token> {
+        if let Some(token) = self.tokens.get(cursor - 1) {
+            Ok(token)
+        } else {
+            ReloxError::new_fatal_error("Parser tried to fetch an unexistent token".to_string())
+        }
+    }
+}
+
+/// Whether the scanner would read the name as a single identifier
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() => {
+            chars.all(|c| c.is_ascii_alphanumeric()) && TokenType::keyword(name).is_none()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::object::ObjString;
+    use crate::bytecode::vm::Vm;
+    use crate::errors::{CompilationError, RuntimeError};
+    use crate::scanner::Scanner;
+
+    use super::*;
+    use std::rc::Rc;
+
+    fn interpret(source: &str) -> Result<Value> {
+        let tokens = Scanner::run_with(source.to_string())?;
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner)?;
+        Vm::run_with(chunk, interner, false)
+    }
+
+    fn string(chars: &str) -> Value {
+        Value::String(Rc::new(ObjString::new(chars.to_string())))
+    }
+
+    #[test]
+    fn test_simple_addition() {
+        let val = interpret("1 + 2").unwrap();
+        assert_eq!(val, Value::Integer(3));
+    }
+
+    #[test]
+    fn test_simple_substraction() {
+        let val = interpret("3 - 2").unwrap();
+        assert_eq!(val, Value::Integer(1));
+    }
+
+    #[test]
+    fn test_addition_with_mult() {
+        let val = interpret("1 + 2 * 3").unwrap();
+        assert_eq!(val, Value::Integer(7));
+    }
+
+    #[test]
+    fn test_addition_with_mult2() {
+        let val = interpret("1 * 3 + 2").unwrap();
+        assert_eq!(val, Value::Integer(5));
+    }
+
+    #[test]
     fn test_parens1() {
-        let tokens = Scanner::run_with("(1 + 3) * 2".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Number(8.0));
+        let val = interpret("(1 + 3) * 2").unwrap();
+        assert_eq!(val, Value::Integer(8));
+    }
+
+    #[test]
+    fn test_parens2() {
+        let val = interpret("(1 + (3 - 1)) * (2 + 2)").unwrap();
+        assert_eq!(val, Value::Integer(12));
+    }
+
+    #[test]
+    fn test_booleans() {
+        let val = interpret("true").unwrap();
+        assert_eq!(val, Value::Bool(true));
+    }
+
+    #[test]
+    fn test_boolean_grouping() {
+        let val = interpret("(true)").unwrap();
+        assert_eq!(val, Value::Bool(true));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let tokens = Scanner::run_with("##$".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+    }
+
+    #[test]
+    fn test_syntax_errors2() {
+        let tokens = Scanner::run_with("((true)".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+    }
+
+    #[test]
+    fn test_left_associative_substraction() {
+        let val = interpret("1 - 2 - 3").unwrap();
+        assert_eq!(val, Value::Integer(-4));
+    }
+
+    #[test]
+    fn test_global_variables() {
+        let val = interpret("var x = 1 + 2; var y; x").unwrap();
+        assert_eq!(val, Value::Integer(3));
+
+        let val = interpret("var y; y").unwrap();
+        assert_eq!(val, Value::Nil);
+    }
+
+    #[test]
+    fn test_global_assignment() {
+        let source = "var x = 1; var y = 2; x = y = x + 10; print x; y";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(11));
+    }
+
+    #[test]
+    fn test_statements_without_trailing_expression() {
+        let val = interpret("var x = 1; x = 2;").unwrap();
+        assert_eq!(val, Value::Nil);
+    }
+
+    #[test]
+    fn test_control_flow_bodies_are_not_trailing_expressions() {
+        let sources = [
+            "if (false) 5",
+            "var i = 0; while (i < 100000) i = i + 1",
+            "for (var i = 0; i < 3; i = i + 1) i",
+            "if (true) 1; else 2",
+        ];
+
+        for source in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+            if let ReloxError::CompilationError(CompilationError { message, .. }) =
+                Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+            {
+                assert_eq!("Expect ';' after expression", message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+
+        let val = interpret("if (false) 5; if (true) {} 6").unwrap();
+        assert_eq!(val, Value::Integer(6));
+    }
+
+    #[test]
+    fn test_undefined_global() {
+        let error = interpret("var x = 1;\nx = y;").expect_err("should've been an error");
+        if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+            assert_eq!(2, line);
+            assert_eq!("Undefined variable 'y'", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+
+        assert!(interpret("z = 3;").is_err());
+    }
+
+    #[test]
+    fn test_invalid_assignment_target() {
+        let tokens = Scanner::run_with("var a = 1; var b = 2; a + b = 3;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+
+        let tokens = Scanner::run_with("var = 3;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+    }
+
+    #[test]
+    fn test_block_scopes() {
+        let source = "var a = 1; { var a = 2; { var b = a + 10; a = b; } print a; } a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(1));
+
+        let source = "var a = 1; { var b = 2; var c = 3; a = b * c; } a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(6));
+    }
+
+    #[test]
+    fn test_locals_are_read_from_stack_slots() {
+        let tokens = Scanner::run_with("{ var a = 1; var b = a; }".to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        assert_eq!(Some(&OpCode::GetLocal { slot: 1 }), chunk.instruction_at(1));
+        assert_eq!(Some(&OpCode::Pop), chunk.instruction_at(2));
+    }
+
+    #[test]
+    fn test_local_redeclaration() {
+        let source = "{\n var a = 1;\n var a = 2;\n}";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+
+        if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+            Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+        {
+            assert_eq!(3, line);
+            assert_eq!("Already a variable with this name in this scope", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+
+        let source = "{ var a = 1; { var a = 2; } }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_ok());
+    }
+
+    #[test]
+    fn test_local_in_own_initializer() {
+        let source = "var a = 1;\n{ var a = a; }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+
+        if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+            Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+        {
+            assert_eq!(2, line);
+            assert_eq!("Can't read local variable in its own initializer", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_unterminated_block() {
+        let tokens = Scanner::run_with("{ var a = 1;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+    }
+
+    #[test]
+    fn test_if_else() {
+        let source = "var a = 1; if (true) { a = 2; } else { a = 3; } a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(2));
+
+        let source = "var a = 1; if (false) a = 2; else a = 3; a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(3));
+
+        let source = "var a = 1; if (false) a = 2; a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(1));
+    }
+
+    #[test]
+    fn test_if_truthiness() {
+        let source = "var a = 0; if (nil) a = a + 1; if (0) a = a + 10; if (a) a = a + 100; a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(110));
+    }
+
+    #[test]
+    fn test_if_leaves_the_stack_clean() {
+        let source =
+            "var d; { var a = 1; if (a) { var b = 2; } else { var c = 3; } var e = 4; d = e; } d";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(4));
+    }
+
+    #[test]
+    fn test_if_syntax_errors() {
+        let tokens = Scanner::run_with("if true) print 1;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+
+        let tokens = Scanner::run_with("if (true print 1;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let source = "var i = 0; var a = true; var b = true;
+            while (a) { i = i + 1; if (b) b = false; else a = false; } i";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(2));
+
+        let source = "var i = 0; while (false) i = i + 1; i";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(0));
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let source = "var i = 0; for (var go = true; go; go = false) { var a = 2; i = i + a; } i";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(2));
+
+        let source = "var i = 0; var go = true; for (i = 10; go;) { go = nil; } i";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(10));
+    }
+
+    #[test]
+    fn test_for_loop_variable_is_scoped() {
+        let source = "for (var i = false; i;) {} i";
+        assert!(interpret(source).is_err());
+    }
+
+    #[test]
+    fn test_loop_syntax_errors() {
+        let tokens = Scanner::run_with("while true) {}".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+
+        let tokens = Scanner::run_with("for (var i = 0; i) {}".to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let source = "var sum = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+              var half = i / 2.0;
+              if (i == 7) break;
+              if (half == 1) continue;
+              sum = sum + i;
+            }
+            var j = 0;
+            while (true) { j = j + 1; if (j < 5) continue; break; }
+            sum + j * 100";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(519));
+    }
+
+    #[test]
+    fn test_labeled_loops() {
+        let source = "var found = nil; var skipped = 0;
+            outer: for (var i = 1; i < 5; i = i + 1) {
+              var row = [i];
+              inner: for (var j = 1; j < 5; j = j + 1) {
+                var product = i * j;
+                if (j > i) continue outer;
+                if (product == 6) { found = [i, j]; break outer; }
+                if (j == 1) continue inner;
+                skipped = skipped + 1;
+              }
+            }
+            [found, skipped]";
+        let val = interpret(source).unwrap();
+        assert_eq!("[[3, 2], 1]", val.to_string());
+    }
+
+    #[test]
+    fn test_jumps_out_of_loops_close_captured_locals() {
+        let source = "var fns = [nil, nil, nil];
+            for (var i = 0; i < 3; i = i + 1) {
+              var captured = i * 10;
+              fun get() { return captured; }
+              fns[i] = get;
+              if (i == 0) continue;
+              if (i == 1) { var inner = 5; fns[2] = get; break; }
+            }
+            fns[0]() + fns[1]() + fns[2]()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(20));
+    }
+
+    #[test]
+    fn test_break_and_continue_errors() {
+        let sources = [
+            ("var a = 1;\nbreak;", "Can't use 'break' outside of a loop"),
+            (
+                "while (true) { fun f() {\ncontinue; } }",
+                "Can't use 'continue' outside of a loop",
+            ),
+            ("a: while (true) {\nbreak b; }", "No loop labeled 'b'"),
+            (
+                "a: while (true) {}\nwhile (true) continue a;",
+                "No loop labeled 'a'",
+            ),
+            ("a:\nprint 1;", "Expect a loop after a label"),
+            ("a:\n{ break a; }", "Expect a loop after a label"),
+            ("while (true) {\nbreak }", "Expect ';' after 'break'"),
+        ];
+
+        for (source, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+
+            if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+                Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+            {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_and() {
+        let cases = vec![
+            ("true and 2", Value::Integer(2)),
+            ("1 and nil", Value::Nil),
+            ("false and 2", Value::Bool(false)),
+            ("nil and true", Value::Nil),
+            ("1 and 2 and 3", Value::Integer(3)),
+        ];
+
+        for (source, expected) in cases {
+            let val = interpret(source).unwrap();
+            assert_eq!(val, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_or() {
+        let cases = vec![
+            ("1 or 2", Value::Integer(1)),
+            ("false or 2", Value::Integer(2)),
+            ("nil or false", Value::Bool(false)),
+            ("nil or false or 3", Value::Integer(3)),
+            ("nil or 1 and 2", Value::Integer(2)),
+            ("1 or 2 and nil", Value::Integer(1)),
+        ];
+
+        for (source, expected) in cases {
+            let val = interpret(source).unwrap();
+            assert_eq!(val, expected, "{}", source);
+        }
     }
 
     #[test]
-    fn test_parens2() {
-        let tokens = Scanner::run_with("(1 + (3 - 1)) * (2 + 2)".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Number(12.0));
+    fn test_short_circuit_skips_right_operand() {
+        let source = "var a = 1; false and (a = 2); true or (a = 3); nil or (a = a + 10); a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(11));
+
+        let source = "var a; a = nil or 2; a";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(2));
     }
 
     #[test]
-    fn test_booleans() {
-        let tokens = Scanner::run_with("true".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
-        assert_eq!(val, Value::Bool(true));
+    fn test_comparisons_and_equality() {
+        let cases = vec![
+            ("1 < 2", Value::Bool(true)),
+            ("2 <= 2", Value::Bool(true)),
+            ("3 <= 2", Value::Bool(false)),
+            ("1 > 2", Value::Bool(false)),
+            ("2 >= 2", Value::Bool(true)),
+            ("1 + 1 == 2", Value::Bool(true)),
+            ("1 != 2", Value::Bool(true)),
+            ("nil == nil", Value::Bool(true)),
+            ("nil == false", Value::Bool(false)),
+            ("nil != false", Value::Bool(true)),
+            ("true == 1", Value::Bool(false)),
+            ("1 < 2 == 2 > 1", Value::Bool(true)),
+        ];
+
+        for (source, expected) in cases {
+            let val = interpret(source).unwrap();
+            assert_eq!(val, expected, "{}", source);
+        }
     }
 
     #[test]
-    fn test_boolean_grouping() {
-        let tokens = Scanner::run_with("(true)".to_string()).unwrap();
-        let chunk = Compiler::run_with(tokens).unwrap();
-        let val = Vm::run_with(chunk, false).unwrap();
+    fn test_bang() {
+        let cases = vec![
+            ("!true", Value::Bool(false)),
+            ("!!true", Value::Bool(true)),
+            ("!(1 == 2)", Value::Bool(true)),
+            ("!false == true", Value::Bool(true)),
+            ("!nil", Value::Bool(true)),
+            ("!0", Value::Bool(false)),
+            ("!\"s\"", Value::Bool(false)),
+        ];
+
+        for (source, expected) in cases {
+            let val = interpret(source).unwrap();
+            assert_eq!(val, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        let cases = vec![
+            ("\"a\" + \"b\"", string("ab")),
+            ("\"a\" + \"b\" == \"ab\"", Value::Bool(true)),
+            ("\"a\" != \"b\"", Value::Bool(true)),
+            ("\"\" == nil", Value::Bool(false)),
+            (
+                "var a = \"foo\"; { var b = a + \"bar\"; a = b + a; } a",
+                string("foobarfoo"),
+            ),
+        ];
+
+        for (source, expected) in cases {
+            let val = interpret(source).unwrap();
+            assert_eq!(val, expected, "{}", source);
+        }
+
+        assert!(interpret("\"a\" + 1").is_err());
+    }
+
+    #[test]
+    fn test_global_names_share_a_constant() {
+        let source = "var a = \"a\"; a = a + \"b\"; print a; a";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+
+        let name_offsets: Vec<u8> = (0..chunk.len())
+            .filter_map(|i| match chunk.instruction_at(i) {
+                Some(OpCode::DefineGlobal { constant_offset })
+                | Some(OpCode::GetGlobal { constant_offset })
+                | Some(OpCode::SetGlobal { constant_offset }) => Some(*constant_offset),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(vec![0, 0, 0, 0, 0], name_offsets);
+
+        // The "a" literal shares the interned string with the global name
+        match (chunk.read_constant(0), chunk.read_constant(1)) {
+            (Some(Value::String(name)), Some(Value::String(literal))) => {
+                assert!(Rc::ptr_eq(name, literal))
+            }
+            constants => panic!("Unexpected constants {:?}", constants),
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let source = "fun add(a, b) { return a + b; }
+            fun noop() {}
+            var c = 3;
+            add(1, 2) + add(c, 4) + add(noop() == nil and 1 or 0, 0)";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(11));
+    }
+
+    #[test]
+    fn test_functions_are_values() {
+        let source = "fun foo() { return 1; } var bar = foo; { var baz = bar; print baz; }
+            bar() + foo()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(2));
+    }
+
+    #[test]
+    fn test_recursion() {
+        let source = "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }
+            fib(15)";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(610));
+    }
+
+    #[test]
+    fn test_local_functions_and_parameters() {
+        let source = "{ var a = 10; fun double(n) { var b = n * 2; return b; } a = double(a); a }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        assert!(Compiler::run_with(tokens, &mut interner).is_err());
+
+        let source = "var result; { var a = 10; fun double(n) { var b = n * 2; return b; }
+            result = double(a); } result";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(20));
+    }
+
+    #[test]
+    fn test_return_from_top_level() {
+        let tokens = Scanner::run_with(
+            "var a = 1;
+return a;"
+                .to_string(),
+        )
+        .unwrap();
+        let mut interner = Interner::new();
+
+        if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+            Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+        {
+            assert_eq!(2, line);
+            assert_eq!("Can't return from top-level code", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let error = interpret(
+            "fun foo(a) {}
+foo(1, 2);",
+        )
+        .expect_err("should've been an error");
+        if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+            assert_eq!(2, line);
+            assert_eq!("Expected 1 arguments but got 2", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+
+        let error = interpret(
+            "var a = 1;
+\"a\"();",
+        )
+        .expect_err("should've been an error");
+        if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+            assert_eq!(2, line);
+            assert_eq!("Can only call functions and classes", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_function_syntax_errors() {
+        for source in [
+            "fun (a) {}",
+            "fun foo(a b) {}",
+            "fun foo(a) return a;",
+            "foo(1, 2;",
+        ] {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+            assert!(Compiler::run_with(tokens, &mut interner).is_err());
+        }
+    }
+
+    #[test]
+    fn test_closures() {
+        let source = "fun makeCounter() {
+              var count = 0;
+              fun counter() { count = count + 1; return count; }
+              return counter;
+            }
+            var a = makeCounter(); var b = makeCounter();
+            a(); a(); b();
+            a() * 10 + b()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(32));
+    }
+
+    #[test]
+    fn test_closures_share_captured_variables() {
+        let source = "var get; var set;
+            fun main() {
+              var a = 1;
+              fun getter() { return a; }
+              fun setter(value) { a = value; }
+              get = getter; set = setter;
+            }
+            main();
+            set(5);
+            get()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(5));
+    }
+
+    #[test]
+    fn test_nested_closures() {
+        let source = "fun outer() {
+              var x = \"outside\";
+              fun middle() { fun inner() { return x; } return inner; }
+              return middle;
+            }
+            outer()()()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, string("outside"));
+    }
+
+    #[test]
+    fn test_block_locals_are_closed() {
+        let source = "var first; var second;
+            for (var i = 0; i < 2; i = i + 1) {
+              var j = i;
+              fun capture() { return j; }
+              if (first == nil) first = capture; else second = capture;
+            }
+            first() * 10 + second()";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+
+        let close_count = (0..chunk.len())
+            .filter(|i| chunk.instruction_at(*i) == Some(&OpCode::CloseUpvalue))
+            .count();
+        assert_eq!(1, close_count);
+
+        let val = Vm::run_with(chunk, interner, false).unwrap();
+        assert_eq!(val, Value::Integer(1));
+    }
+
+    #[test]
+    fn test_classes_and_fields() {
+        let source = "class Pair {}
+            var pair = Pair();
+            pair.first = 1;
+            pair.second = pair.first + 1;
+            pair.first + pair.second";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(3));
+    }
+
+    #[test]
+    fn test_methods_and_this() {
+        let source = "class Counter {
+              init(start) { this.count = start; }
+              increment() { this.count = this.count + 1; return this; }
+              getter() { fun get() { return this.count; } return get; }
+            }
+            var counter = Counter(10);
+            var increment = counter.increment;
+            increment();
+            counter.increment().increment();
+            counter.getter()()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, Value::Integer(13));
+    }
+
+    #[test]
+    fn test_initializers_return_the_instance() {
+        let source = "class Foo { init() { this.a = 1; return; } }
+            var foo = Foo();
+            foo.init() == foo";
+        let val = interpret(source).unwrap();
         assert_eq!(val, Value::Bool(true));
+
+        let source = "class Foo {\n init() { return 1; } }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+
+        if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+            Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+        {
+            assert_eq!(2, line);
+            assert_eq!("Can't return a value from an initializer", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
     }
 
     #[test]
-    fn test_syntax_errors() {
-        let tokens = Scanner::run_with("##$".to_string()).unwrap();
-        assert!(Compiler::run_with(tokens).is_err());
+    fn test_this_outside_of_a_class() {
+        for source in ["this;", "fun foo() { return this; }"] {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+
+            if let ReloxError::CompilationError(CompilationError { message, .. }) =
+                Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+            {
+                assert_eq!("Can't use 'this' outside of a class", message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
     }
 
     #[test]
-    fn test_syntax_errors2() {
-        let tokens = Scanner::run_with("((true)".to_string()).unwrap();
-        assert!(Compiler::run_with(tokens).is_err());
+    fn test_property_errors() {
+        let sources = [
+            ("class Foo {}\nFoo().bar;", "Undefined property 'bar'"),
+            ("var a = 1;\na.bar;", "Only instances have properties"),
+            ("var a = 1;\na.bar = 2;", "Only instances have fields"),
+            ("class Foo {}\nFoo(1);", "Expected 0 arguments but got 1"),
+            (
+                "class Foo { init(a) {} }\nFoo();",
+                "Expected 1 arguments but got 0",
+            ),
+        ];
+
+        for (source, expected_message) in sources {
+            let error = interpret(source).expect_err("should've been an error");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let source = "class A {
+              init(value) { this.value = value; }
+              name() { return \"A\"; }
+              describe() { return this.name() + this.value; }
+            }
+            class B < A {
+              init() { super.init(\"!\"); }
+              name() { return \"B\" + super.name(); }
+            }
+            class C < B {}
+            C().describe()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, string("BA!"));
+    }
+
+    #[test]
+    fn test_super_is_bound_statically() {
+        let source = "class A { method() { return \"A\"; } }
+            class B < A { method() { return \"B\"; } test() { return super.method(); } }
+            class C < B {}
+            var test = C().test;
+            test()";
+        let val = interpret(source).unwrap();
+        assert_eq!(val, string("A"));
+    }
+
+    #[test]
+    fn test_inheritance_errors() {
+        let sources = [
+            (
+                "class A {}\nclass B { foo() { super.foo(); } }",
+                2,
+                "Can't use 'super' in a class with no superclass",
+            ),
+            (
+                "var a = 1;\nsuper.foo();",
+                2,
+                "Can't use 'super' outside of a class",
+            ),
+            (
+                "class A {}\nclass A < A {}",
+                2,
+                "A class can't inherit from itself",
+            ),
+        ];
+
+        for (source, expected_line, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+
+            if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+                Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+            {
+                assert_eq!(expected_line, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+
+        let error = interpret("var A = 1;\nclass B < A {}").expect_err("should've been an error");
+        if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+            assert_eq!(2, line);
+            assert_eq!("Superclass must be a class", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+
+        let error = interpret("class A {}\nclass B < A { foo() { super.bar(); } }\nB().foo();")
+            .expect_err("should've been an error");
+        if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+            assert_eq!(2, line);
+            assert_eq!("Undefined property 'bar'", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_lists() {
+        let source = "var xs = [1, 2, [3, 4]];
+            xs[0] = xs[1] + xs[2][1];
+            var ys = xs;
+            ys[2][0] = \"three\";
+            [xs[0], ys[2][0], xs == ys, [] == []]";
+        let val = interpret(source).unwrap();
+        assert_eq!("[6, three, true, false]", val.to_string());
+    }
+
+    #[test]
+    fn test_integers_and_floats() {
+        let source = "var m = {1: \"one\", 2.5: \"two and a half\"};
+            m[1.0] = \"uno\";
+            [16777217, 7 / 2, -7 / 2, 7 / 2.0, 1 + 0.5, 2 * 1.0, 1 == 1.0, 2 < 2.5,
+             0.1 + 0.2 == 0.3, 1.0 / 0, -9223372036854775807 - 1, m, len(m)]";
+        let val = interpret(source).unwrap();
+        assert_eq!(
+            "[16777217, 3, -3, 3.5, 1.5, 2.0, true, true, false, inf, -9223372036854775808, \
+            {1: uno, 2.5: two and a half}, 2]",
+            val.to_string()
+        );
+
+        let sources = [
+            ("var a = 1;\na / 0;", "Integer division by zero"),
+            ("var a = 9223372036854775807;\na + 1;", "Integer overflow"),
+            ("var a = -9223372036854775807 - 1;\n-a;", "Integer overflow"),
+            (
+                "var a = -9223372036854775807 - 1;\na / -1;",
+                "Integer overflow",
+            ),
+            ("var a = 4294967296;\na * a;", "Integer overflow"),
+        ];
+
+        for (source, expected_message) in sources {
+            let error = interpret(source).expect_err("should've failed");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+
+        let source = "var error; try { 1 / 0; } catch (e) { error = e.message; } error";
+        let val = interpret(source).unwrap();
+        assert_eq!("Integer division by zero", val.to_string());
+    }
+
+    #[test]
+    fn test_modulo_power_and_bitwise() {
+        let source =
+            "[7 % 3, -7 % 3, 7.5 % 2, 2 ** 10, 2 ** 3 ** 2, -2 ** 2, 2 ** -1, 1 ** 5000000001,
+             6 & 3, 6 | 3, 6 ^ 3, ~5, 1 << 63, -16 >> 2, 1 + 2 << 1, 12345 & 0xFF == 57]";
+        let val = interpret(source).unwrap();
+        assert_eq!(
+            "[1, -1, 1.5, 1024, 512, -4, 0.5, 1, 2, 7, 5, -6, -9223372036854775808, -4, 6, true]",
+            val.to_string()
+        );
+
+        let source = "fun djb2(bytes) {
+              var hash = 5381;
+              for (var i = 0; i < len(bytes); i = i + 1) {
+                hash = ((hash << 5) + hash + bytes[i]) & 0xFFFF_FFFF;
+              }
+              return hash;
+            }
+            var hash = djb2([104, 101, 108, 108, 111]);
+            [hash, hash ^ (hash >> 16)]";
+        let val = interpret(source).unwrap();
+        assert_eq!("[261238937, 261242635]", val.to_string());
+
+        let sources = [
+            ("var a = 1;\na % 0;", "Integer modulo by zero"),
+            ("var a = 2;\na ** 63;", "Integer overflow"),
+            (
+                "var a = 1.5;\na & 1;",
+                "Operands of '&' must be integers, got 1.5 and 1",
+            ),
+            (
+                "var a = 1;\na << 64;",
+                "Shift amount must be between 0 and 63, got 64",
+            ),
+            (
+                "var a = 1;\na >> -1;",
+                "Shift amount must be between 0 and 63, got -1",
+            ),
+            (
+                "var a = 1.0;\n~a;",
+                "Operand of '~' must be an integer, got 1.0",
+            ),
+        ];
+
+        for (source, expected_message) in sources {
+            let error = interpret(source).expect_err("should've failed");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let source = "var n = 2;
+            var m = {\"k\": [n, nil]};
+            fun greet(who) { return \"hi ${who}\"; }
+            \"${n}+${n} is ${n + n}, ${m} ${greet(\"${true}\")}\\t\\u{263A}\"";
+        let val = interpret(source).unwrap();
+        assert_eq!("2+2 is 4, {k: [2, nil]} hi true\t\u{263A}", val.to_string());
+
+        let tokens = Scanner::run_with("\"a ${1 2}\";".to_string()).unwrap();
+        let mut interner = Interner::new();
+        let error = Compiler::run_with(tokens, &mut interner).unwrap_err();
+        if let ReloxError::CompilationError(CompilationError { message, .. }) = error {
+            assert_eq!("Expect '}' after interpolated expression", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_list_errors() {
+        let sources = [
+            ("var a = 1;\na[0];", "Can only index lists and maps"),
+            (
+                "var a = [1];\na[0.5];",
+                "List index must be a whole number, got 0.5",
+            ),
+            (
+                "var a = [1];\na[1] = 2;",
+                "Index 1 out of range for a list of length 1",
+            ),
+            (
+                "var a = [1];\na[-1];",
+                "Index -1 out of range for a list of length 1",
+            ),
+            ("var a = [1];\na[\"0\"];", "List index must be a number"),
+        ];
+
+        for (source, expected_message) in sources {
+            let error = interpret(source).expect_err("should've been an error");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+
+        let sources = [
+            ("var a = [1,\n2;", "Expect ']' after list items"),
+            ("var a = [1];\na[0;", "Expect ']' after index"),
+        ];
+
+        for (source, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+
+            if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+                Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+            {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_maps() {
+        let source = "var ages = {\"ann\": 30, \"bob\": 25 + 1, 0: \"zero\"};
+            ages[\"c\" + \"ar\"] = 40;
+            ages[\"bob\"] = ages[\"bob\"] + 1;
+            ages[-0] = ages[0] + \"!\";
+            delete(ages, \"ann\");
+            var names = keys(ages);
+            var total = 0;
+            for (var i = 0; i < len(names); i = i + 1) {
+              if (names[i] != 0) total = total + ages[names[i]];
+            }
+            [total, names, has(ages, \"ann\"), has(ages, 0), ages[0], {}, len({nil: 1, true: 2})]";
+        let val = interpret(source).unwrap();
+        assert_eq!(
+            "[67, [bob, 0, car], false, true, zero!, {}, 2]",
+            val.to_string()
+        );
+    }
+
+    #[test]
+    fn test_map_errors() {
+        let sources = [
+            ("var a = {};\na[\"b\"];", "Undefined key 'b'"),
+            ("var a = {};\na[0.0/0] = 1;", "NaN can't be a map key"),
+            (
+                "var a = {};\na[[]] = 1;",
+                "Map keys must be nil, booleans, numbers or strings, got []",
+            ),
+            (
+                "var a = {\n[]: 1};",
+                "Map keys must be nil, booleans, numbers or strings, got []",
+            ),
+            ("var a = [];\nhas(a, 1);", "has expects a map, got []"),
+        ];
+
+        for (source, expected_message) in sources {
+            let error = interpret(source).expect_err("should've been an error");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+
+        let sources = [
+            ("var a = {1\n2};", "Expect ':' after map key"),
+            ("var a = {1: 2\n3: 4};", "Expect '}' after map entries"),
+        ];
+
+        for (source, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut interner = Interner::new();
+
+            if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+                Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+            {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_catch() {
+        let source = "var caught = [nil, nil, nil, nil];
+            try { caught[0] = 1 + nil; } catch (e) { caught[0] = [e.message, e.line]; }
+            fun fail(depth) { if (depth == 0) throw \"deep\"; fail(depth - 1); return 1; }
+            fun wrapped() { var local = 1; try { return fail(3); } catch (e) { return e; } }
+            caught[1] = wrapped();
+            try {
+              try { throw 1; } catch (e) { throw e + 1; }
+            } catch (e) { caught[2] = e; }
+            try { var m = {}; m[\"missing\"]; } catch (e) { caught[3] = e; }
+            caught";
+        let val = interpret(source).unwrap();
+        assert_eq!(
+            "[[Tried to add oranges with apples, 2], deep, 2, [line 9] Error: Undefined key 'missing']",
+            val.to_string()
+        );
+    }
+
+    #[test]
+    fn test_finally() {
+        let source = "var log = [nil, nil, nil, nil, nil];
+            try { log[0] = 1; } finally { log[0] = log[0] + 1; }
+            try {
+              try { throw \"inner\"; } finally { log[1] = \"cleaned\"; }
+            } catch (e) { log[2] = e; }
+            try {
+              try { throw \"first\"; } catch (e) { throw e + \" again\"; } finally { log[3] = 1; }
+            } catch (e) { log[3] = [log[3], e]; }
+            fun early() {
+              try { return \"returned\"; } finally { log[4] = \"ran\"; }
+              return \"fell through\";
+            }
+            [log, early(), log[4]]";
+        let val = interpret(source).unwrap();
+        assert_eq!(
+            "[[2, cleaned, inner, [1, first again], ran], returned, ran]",
+            val.to_string()
+        );
+    }
+
+    #[test]
+    fn test_finally_runs_when_jumping_out_of_loops() {
+        let source = "var runs = 0; var i = 0;
+            outer: while (true) {
+              i = i + 1;
+              for (var j = 0; j < 3; j = j + 1) {
+                try {
+                  var inner = j;
+                  if (inner == 1) continue;
+                  if (i == 2) break outer;
+                } finally { runs = runs + 1; }
+              }
+            }
+            [i, runs]";
+        let val = interpret(source).unwrap();
+        assert_eq!("[2, 4]", val.to_string());
+    }
+
+    #[test]
+    fn test_finally_that_throws_when_jumping_runs_once() {
+        let source = "var runs = 0; var caught = \"\";
+            try {
+              while (true) { try { break; } finally { runs = runs + 1; throw \"break\"; } }
+            } catch (e) { caught = caught + e; }
+            var i = 0;
+            try {
+              while (i < 3) {
+                i = i + 1;
+                try { continue; } finally { runs = runs + 1; throw \" continue\"; }
+              }
+            } catch (e) { caught = caught + e; }
+            fun f() { try { return 1; } finally { runs = runs + 1; throw \" return\"; } }
+            try { f(); } catch (e) { caught = caught + e; }
+            try {
+              while (true) {
+                try { break; } catch (e) { caught = caught + \" own catch\"; }
+                finally { runs = runs + 1; throw \" catch\"; }
+              }
+            } catch (e) { caught = caught + e; }
+            [runs, i, caught]";
+        let val = interpret(source).unwrap();
+        assert_eq!("[4, 1, break continue return catch]", val.to_string());
+    }
+
+    #[test]
+    fn test_exception_errors() {
+        let tokens = Scanner::run_with("var a = 1;\ntry {} a = 2;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        if let ReloxError::CompilationError(CompilationError { line, message, .. }) =
+            Compiler::run_with(tokens, &mut interner).expect_err("should've been an error")
+        {
+            assert_eq!(2, line);
+            assert_eq!("Expect 'catch' or 'finally' after try block", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+
+        let sources = [
+            ("var a = 1;\nthrow \"boom\";", 2, "Uncaught exception: boom"),
+            (
+                "try { 1 + nil; }\ncatch (e) { throw e; }",
+                1,
+                "Tried to add oranges with apples",
+            ),
+            (
+                "try { throw 1; }\nfinally { var a = 2; }",
+                2,
+                "Uncaught exception: 1",
+            ),
+            (
+                "try { throw 1; } catch (e) {\ne.message; }",
+                2,
+                "Only instances have properties",
+            ),
+        ];
+
+        for (source, expected_line, expected_message) in sources {
+            let error = interpret(source).expect_err("should've been an error");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(expected_line, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    /// A directory in the system temp dir, removed along with its files when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn join(&self, path: &str) -> PathBuf {
+            self.0.join(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes the files under a fresh directory in the system temp dir
+    fn write_files(dir_name: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir(std::env::temp_dir().join(format!(
+            "relox-{}-{}",
+            dir_name,
+            std::process::id()
+        )));
+        for (path, source) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    fn compile_file(path: &Path, interner: &mut Interner) -> Result<Chunk> {
+        let tokens = Scanner::run_with(fs::read_to_string(path).unwrap()).unwrap();
+        Compiler::run_file(tokens, interner, path)
+    }
+
+    #[test]
+    fn test_imports() {
+        let dir = write_files(
+            "imports",
+            &[
+                (
+                    "main.lox",
+                    "import \"lib/counter.lox\";
+                    import \"lib/user.lox\";
+                    var count = 100;
+                    counter.increment();
+                    [counter.count, user.seen, count, user.counter == counter, len([])]",
+                ),
+                (
+                    "lib/counter.lox",
+                    "var count = 0; fun increment() { count = count + 1; return count; }",
+                ),
+                (
+                    "lib/user.lox",
+                    "import \"counter.lox\"; counter.increment(); var seen = counter.count;",
+                ),
+            ],
+        );
+
+        let mut interner = Interner::new();
+        let chunk = compile_file(&dir.join("main.lox"), &mut interner).unwrap();
+        let val = Vm::run_with(chunk, interner, false).unwrap();
+        assert_eq!("[2, 1, 100, true, 0]", val.to_string());
+    }
+
+    #[test]
+    fn test_circular_imports() {
+        let dir = write_files(
+            "circular-imports",
+            &[
+                ("a.lox", "import \"b.lox\";"),
+                ("b.lox", "var x = 1;\nimport \"c/c.lox\";"),
+                ("c/c.lox", "\n\nimport \"../b.lox\";"),
+            ],
+        );
+
+        let mut interner = Interner::new();
+        let error = compile_file(&dir.join("a.lox"), &mut interner).unwrap_err();
+        if let ReloxError::CompilationError(CompilationError { line, message, .. }) = error {
+            let b = dir.join("b.lox").display().to_string();
+            let c = dir.join("c/c.lox").display().to_string();
+            let back_to_b = dir.join("c/../b.lox").display().to_string();
+            assert_eq!(3, line);
+            assert_eq!(
+                format!("Circular import: {} -> {} -> {}", b, c, back_to_b),
+                message
+            );
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = write_files(
+            "import-errors",
+            &[
+                ("missing.lox", "var a = 1;\nimport \"nope.lox\";"),
+                ("nested.lox", "{\nimport \"ok.lox\"; }"),
+                ("badName.lox", "var a = 1;\nimport \"bad-name.lox\";"),
+                ("bad-name.lox", ""),
+                ("ok.lox", "var a = 1;"),
+                ("broken.lox", "var a = 1;\nimport \"syntax.lox\";"),
+                ("syntax.lox", "var a = 1;\n\nvar = 2;"),
+            ],
+        );
+        let cases = [
+            ("missing.lox", 2, "Can't find module"),
+            (
+                "nested.lox",
+                2,
+                "Can only import at the top level of a file",
+            ),
+            (
+                "badName.lox",
+                2,
+                "Module 'bad-name.lox' isn't named like a variable",
+            ),
+            ("broken.lox", 3, "Expect variable name"),
+        ];
+
+        for (file, expected_line, expected_message) in cases {
+            let mut interner = Interner::new();
+            let error = compile_file(&dir.join(file), &mut interner).unwrap_err();
+            if let ReloxError::CompilationError(CompilationError { line, message, .. }) = error {
+                assert_eq!(expected_line, line);
+                assert!(
+                    message.starts_with(expected_message),
+                    "{} doesn't start with {}",
+                    message,
+                    expected_message
+                );
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+
+        let tokens = Scanner::run_with("import \"ok.lox\";\nok.b".to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_file(tokens, &mut interner, &dir.join("main.lox")).unwrap();
+        let error = Vm::run_with(chunk, interner, false).expect_err("should've been an error");
+        if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+            assert_eq!(2, line);
+            assert!(message.starts_with("Undefined property 'b' of <module"));
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
     }
 }
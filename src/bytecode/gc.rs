@@ -0,0 +1,482 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::rc::{Rc, Weak};
+
+use super::object::{
+    ObjBoundMethod, ObjClass, ObjClosure, ObjInstance, ObjList, ObjMap, ObjUpvalue,
+};
+use super::value::Value;
+
+const GC_HEAP_GROW_FACTOR: usize = 2;
+const FIRST_GC_THRESHOLD: usize = 1024 * 1024;
+
+/// An object allocated by the vm at runtime. The heap only keeps a weak handle, objects are
+/// owned by the values pointing to them.
+#[derive(Debug)]
+pub enum HeapObject {
+    Closure(Weak<ObjClosure>),
+    Upvalue(Weak<RefCell<ObjUpvalue>>),
+    Class(Weak<ObjClass>),
+    Instance(Weak<ObjInstance>),
+    BoundMethod(Weak<ObjBoundMethod>),
+    List(Weak<ObjList>),
+    Map(Weak<ObjMap>),
+}
+
+impl HeapObject {
+    fn size(&self) -> usize {
+        match self {
+            HeapObject::Closure(_) => size_of::<ObjClosure>(),
+            HeapObject::Upvalue(_) => size_of::<RefCell<ObjUpvalue>>(),
+            HeapObject::Class(_) => size_of::<ObjClass>(),
+            HeapObject::Instance(_) => size_of::<ObjInstance>(),
+            HeapObject::BoundMethod(_) => size_of::<ObjBoundMethod>(),
+            HeapObject::List(list) => {
+                size_of::<ObjList>()
+                    + list
+                        .upgrade()
+                        .map_or(0, |list| list.items.borrow().len() * size_of::<Value>())
+            }
+            HeapObject::Map(map) => {
+                size_of::<ObjMap>()
+                    + map
+                        .upgrade()
+                        .map_or(0, |map| map.len() * 2 * size_of::<Value>())
+            }
+        }
+    }
+
+    fn address(&self) -> Option<usize> {
+        match self {
+            HeapObject::Closure(object) => address_of_weak(object),
+            HeapObject::Upvalue(object) => address_of_weak(object),
+            HeapObject::Class(object) => address_of_weak(object),
+            HeapObject::Instance(object) => address_of_weak(object),
+            HeapObject::BoundMethod(object) => address_of_weak(object),
+            HeapObject::List(object) => address_of_weak(object),
+            HeapObject::Map(object) => address_of_weak(object),
+        }
+    }
+
+    /// Clears the references an unreachable object holds, so the reference cycles it's part of
+    /// are broken and the reference counts free it.
+    fn release(&self) {
+        match self {
+            HeapObject::Upvalue(upvalue) => {
+                if let Some(upvalue) = upvalue.upgrade() {
+                    *upvalue.borrow_mut() = ObjUpvalue::Closed(Value::Nil);
+                }
+            }
+            HeapObject::Class(class) => {
+                if let Some(class) = class.upgrade() {
+                    class.methods.borrow_mut().clear();
+                }
+            }
+            HeapObject::Instance(instance) => {
+                if let Some(instance) = instance.upgrade() {
+                    instance.fields.borrow_mut().clear();
+                }
+            }
+            HeapObject::List(list) => {
+                if let Some(list) = list.upgrade() {
+                    list.items.borrow_mut().clear();
+                }
+            }
+            HeapObject::Map(map) => {
+                if let Some(map) = map.upgrade() {
+                    map.clear();
+                }
+            }
+            // Closures and bound methods can only be part of a cycle through upvalues,
+            // methods or fields, which are released on their own
+            HeapObject::Closure(_) | HeapObject::BoundMethod(_) => {}
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        self.address().is_some()
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            HeapObject::Closure(object) => object.strong_count(),
+            HeapObject::Upvalue(object) => object.strong_count(),
+            HeapObject::Class(object) => object.strong_count(),
+            HeapObject::Instance(object) => object.strong_count(),
+            HeapObject::BoundMethod(object) => object.strong_count(),
+            HeapObject::List(object) => object.strong_count(),
+            HeapObject::Map(object) => object.strong_count(),
+        }
+    }
+
+    /// The addresses of the tracked objects this one points to, once per reference
+    fn references(&self) -> Vec<usize> {
+        let mut references = Vec::new();
+        match self {
+            HeapObject::Closure(closure) => {
+                if let Some(closure) = closure.upgrade() {
+                    references.extend(closure.upvalues.iter().map(address_of));
+                }
+            }
+            HeapObject::Upvalue(upvalue) => {
+                if let Some(upvalue) = upvalue.upgrade() {
+                    if let ObjUpvalue::Closed(value) = &*upvalue.borrow() {
+                        references.extend(tracked_address(value));
+                    }
+                }
+            }
+            HeapObject::Class(class) => {
+                if let Some(class) = class.upgrade() {
+                    references.extend(class.methods.borrow().values().map(address_of));
+                }
+            }
+            HeapObject::Instance(instance) => {
+                if let Some(instance) = instance.upgrade() {
+                    references.push(address_of(&instance.class));
+                    references.extend(
+                        instance
+                            .fields
+                            .borrow()
+                            .values()
+                            .filter_map(tracked_address),
+                    );
+                }
+            }
+            HeapObject::BoundMethod(bound) => {
+                if let Some(bound) = bound.upgrade() {
+                    references.extend(tracked_address(&bound.receiver));
+                    references.push(address_of(&bound.method));
+                }
+            }
+            HeapObject::List(list) => {
+                if let Some(list) = list.upgrade() {
+                    references.extend(list.items.borrow().iter().filter_map(tracked_address));
+                }
+            }
+            HeapObject::Map(map) => {
+                if let Some(map) = map.upgrade() {
+                    references.extend(map.values().iter().filter_map(tracked_address));
+                }
+            }
+        }
+        references
+    }
+}
+
+/// Heap
+///
+/// Mark and sweep collector for the objects the vm allocates. Reference counting frees most
+/// of them as soon as they're unused, the collector takes care of the reference cycles, like
+/// two instances pointing to each other. A collection is due when the allocated bytes grow
+/// past a threshold that's set relative to the heap size left after the previous collection.
+/// In stress mode every allocation triggers a collection.
+#[derive(Debug)]
+pub struct Heap {
+    objects: Vec<HeapObject>,
+    bytes_allocated: usize,
+    next_gc: usize,
+    stress: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: FIRST_GC_THRESHOLD,
+            stress: false,
+        }
+    }
+
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+        if stress {
+            self.next_gc = self.bytes_allocated;
+        }
+    }
+
+    pub fn track(&mut self, object: HeapObject) {
+        self.allocated(object.size());
+        self.objects.push(object);
+    }
+
+    /// Accounts for memory allocated outside of the tracked objects, like strings
+    pub fn allocated(&mut self, bytes: usize) {
+        self.bytes_allocated += bytes;
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.bytes_allocated > self.next_gc
+    }
+
+    /// Releases the tracked objects that weren't marked and are only kept alive by each other.
+    /// Unmarked objects can still be in use outside of the vm, like a list returned to the host,
+    /// so the references between them are subtracted from their reference counts first. The
+    /// ones left with references from the outside, and everything they point to, are kept.
+    pub fn sweep(&mut self, marker: &Marker) {
+        let mut unmarked = HashMap::new();
+        for object in self.objects.iter() {
+            match object.address() {
+                Some(address) if !marker.is_marked(address) => {
+                    unmarked.insert(address, object);
+                }
+                _ => {}
+            }
+        }
+
+        let mut outside_references: HashMap<usize, usize> = unmarked
+            .iter()
+            .map(|(address, object)| (*address, object.strong_count()))
+            .collect();
+        for object in unmarked.values() {
+            for reference in object.references() {
+                if let Some(count) = outside_references.get_mut(&reference) {
+                    *count = count.saturating_sub(1);
+                }
+            }
+        }
+
+        let mut gray: Vec<usize> = outside_references
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, _)| *address)
+            .collect();
+        let mut kept: HashSet<usize> = gray.iter().copied().collect();
+        while let Some(address) = gray.pop() {
+            for reference in unmarked[&address].references() {
+                if unmarked.contains_key(&reference) && kept.insert(reference) {
+                    gray.push(reference);
+                }
+            }
+        }
+
+        for (address, object) in unmarked.iter() {
+            if !kept.contains(address) {
+                object.release();
+            }
+        }
+
+        self.objects.retain(HeapObject::is_alive);
+    }
+
+    /// Sets the threshold for the next collection from the heap size left after a sweep.
+    /// `untracked_bytes` is the memory in use outside of the tracked objects.
+    pub fn adjust_threshold(&mut self, untracked_bytes: usize) {
+        let tracked_bytes: usize = self.objects.iter().map(HeapObject::size).sum();
+        self.bytes_allocated = tracked_bytes + untracked_bytes;
+        self.next_gc = match self.stress {
+            true => self.bytes_allocated,
+            false => (self.bytes_allocated * GC_HEAP_GROW_FACTOR).max(FIRST_GC_THRESHOLD),
+        };
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    #[cfg(test)]
+    pub fn live_objects(&self) -> usize {
+        self.objects
+            .iter()
+            .filter(|object| object.is_alive())
+            .count()
+    }
+}
+
+/// Marker
+///
+/// Walks the object graph from the roots given by the vm, remembering the address of every
+/// reachable object.
+#[derive(Debug, Default)]
+pub struct Marker {
+    marked: HashSet<usize>,
+    gray: Vec<Value>,
+}
+
+impl Marker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        let address = match value {
+            Value::Closure(object) => address_of(object),
+            Value::Class(object) => address_of(object),
+            Value::Instance(object) => address_of(object),
+            Value::BoundMethod(object) => address_of(object),
+            Value::List(object) => address_of(object),
+            Value::Map(object) => address_of(object),
+            Value::Module(object) => address_of(object),
+            // Strings, natives and functions can't point to a tracked object
+            _ => return,
+        };
+
+        if self.marked.insert(address) {
+            self.gray.push(value.clone());
+        }
+    }
+
+    pub fn mark_upvalue(&mut self, upvalue: &Rc<RefCell<ObjUpvalue>>) {
+        if !self.marked.insert(address_of(upvalue)) {
+            return;
+        }
+
+        // Open upvalues point to the stack, which is a root already
+        if let ObjUpvalue::Closed(value) = &*upvalue.borrow() {
+            self.mark_value(value);
+        }
+    }
+
+    /// Marks everything reachable from the values marked so far
+    pub fn trace(&mut self) {
+        while let Some(value) = self.gray.pop() {
+            match value {
+                Value::Closure(closure) => {
+                    for upvalue in closure.upvalues.iter() {
+                        self.mark_upvalue(upvalue);
+                    }
+                    if let Some(module) = &closure.module {
+                        self.mark_value(&Value::Module(Rc::clone(module)));
+                    }
+                }
+                // Modules are never freed, the vm keeps them for later imports
+                Value::Module(module) => {
+                    for value in module.globals.borrow().values() {
+                        self.mark_value(value);
+                    }
+                }
+                Value::Class(class) => {
+                    for method in class.methods.borrow().values() {
+                        self.mark_value(&Value::Closure(Rc::clone(method)));
+                    }
+                }
+                Value::Instance(instance) => {
+                    self.mark_value(&Value::Class(Rc::clone(&instance.class)));
+                    for field in instance.fields.borrow().values() {
+                        self.mark_value(field);
+                    }
+                }
+                Value::BoundMethod(bound) => {
+                    self.mark_value(&bound.receiver);
+                    self.mark_value(&Value::Closure(Rc::clone(&bound.method)));
+                }
+                Value::List(list) => {
+                    for item in list.items.borrow().iter() {
+                        self.mark_value(item);
+                    }
+                }
+                // Keys can't point to tracked objects, only the values are traced
+                Value::Map(map) => {
+                    for value in map.values() {
+                        self.mark_value(&value);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn is_marked(&self, address: usize) -> bool {
+        self.marked.contains(&address)
+    }
+}
+
+fn address_of<T>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+/// The address of the object a value points to, if it's one the heap tracks
+fn tracked_address(value: &Value) -> Option<usize> {
+    match value {
+        Value::Closure(object) => Some(address_of(object)),
+        Value::Class(object) => Some(address_of(object)),
+        Value::Instance(object) => Some(address_of(object)),
+        Value::BoundMethod(object) => Some(address_of(object)),
+        Value::List(object) => Some(address_of(object)),
+        Value::Map(object) => Some(address_of(object)),
+        _ => None,
+    }
+}
+
+fn address_of_weak<T>(object: &Weak<T>) -> Option<usize> {
+    object.upgrade().map(|object| address_of(&object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::interner::{InternedKey, Interner};
+    use pretty_assertions::assert_eq;
+
+    fn instance(class: &Rc<ObjClass>, heap: &mut Heap) -> Rc<ObjInstance> {
+        let instance = Rc::new(ObjInstance::new(Rc::clone(class)));
+        heap.track(HeapObject::Instance(Rc::downgrade(&instance)));
+        instance
+    }
+
+    #[test]
+    fn test_unreachable_cycles_are_freed() {
+        let mut interner = Interner::new();
+        let mut heap = Heap::new();
+        let class = Rc::new(ObjClass::new(interner.intern("Node")));
+        heap.track(HeapObject::Class(Rc::downgrade(&class)));
+
+        let field = InternedKey(interner.intern("other"));
+        let a = instance(&class, &mut heap);
+        let b = instance(&class, &mut heap);
+        let c = instance(&class, &mut heap);
+        a.fields
+            .borrow_mut()
+            .insert(field.clone(), Value::Instance(Rc::clone(&b)));
+        b.fields
+            .borrow_mut()
+            .insert(field, Value::Instance(Rc::clone(&a)));
+
+        let weak_a = Rc::downgrade(&a);
+        drop(a);
+        drop(b);
+        assert!(weak_a.upgrade().is_some());
+        assert_eq!(4, heap.live_objects());
+
+        let mut marker = Marker::new();
+        marker.mark_value(&Value::Instance(Rc::clone(&c)));
+        marker.trace();
+        heap.sweep(&marker);
+        heap.adjust_threshold(0);
+
+        assert!(weak_a.upgrade().is_none());
+        assert_eq!(2, heap.live_objects());
+        assert_eq!(
+            size_of::<ObjClass>() + size_of::<ObjInstance>(),
+            heap.bytes_allocated()
+        );
+    }
+
+    #[test]
+    fn test_threshold_grows_with_the_heap() {
+        let mut heap = Heap::new();
+        heap.allocated(FIRST_GC_THRESHOLD);
+        assert!(!heap.should_collect());
+        heap.allocated(1);
+        assert!(heap.should_collect());
+
+        heap.sweep(&Marker::new());
+        heap.adjust_threshold(FIRST_GC_THRESHOLD);
+        assert!(!heap.should_collect());
+        heap.allocated(FIRST_GC_THRESHOLD + 1);
+        assert!(heap.should_collect());
+    }
+
+    #[test]
+    fn test_stress_mode_collects_on_every_allocation() {
+        let mut heap = Heap::new();
+        heap.set_stress(true);
+        assert!(!heap.should_collect());
+        heap.allocated(1);
+        assert!(heap.should_collect());
+        heap.sweep(&Marker::new());
+        heap.adjust_threshold(0);
+        assert!(!heap.should_collect());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use super::object::ObjString;
+
+/// Interner
+///
+/// Deduplicates the strings used by a program. It's owned by the vm and lent to the compiler,
+/// so string constants, identifiers and strings built at runtime all share the same table and
+/// two equal strings are always the same `Rc`. That makes equality a pointer comparison.
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashMap<String, Rc<ObjString>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self {
+            strings: HashMap::new(),
+        }
+    }
+
+    pub fn intern(&mut self, chars: &str) -> Rc<ObjString> {
+        if let Some(interned) = self.strings.get(chars) {
+            return Rc::clone(interned);
+        }
+
+        self.insert(chars.to_string())
+    }
+
+    pub fn intern_owned(&mut self, chars: String) -> Rc<ObjString> {
+        if let Some(interned) = self.strings.get(&chars) {
+            return Rc::clone(interned);
+        }
+
+        self.insert(chars)
+    }
+
+    /// Forgets the strings nothing else points to anymore, which frees them
+    pub fn sweep(&mut self) {
+        self.strings
+            .retain(|_, interned| Rc::strong_count(interned) > 1);
+    }
+
+    /// Memory used by the interned strings
+    pub fn heap_size(&self) -> usize {
+        self.strings
+            .keys()
+            .map(|chars| std::mem::size_of::<ObjString>() + 2 * chars.len())
+            .sum()
+    }
+
+    fn insert(&mut self, chars: String) -> Rc<ObjString> {
+        let interned = Rc::new(ObjString::new(chars.clone()));
+        self.strings.insert(chars, Rc::clone(&interned));
+        interned
+    }
+}
+
+/// Interned string used as a key in hash tables, like the vm globals. Since every string is
+/// interned it's hashed and compared by address instead of by content.
+#[derive(Debug, Clone)]
+pub struct InternedKey(pub Rc<ObjString>);
+
+impl PartialEq for InternedKey {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for InternedKey {}
+
+impl Hash for InternedKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interning_returns_the_same_handle() {
+        let mut interner = Interner::new();
+        let a = interner.intern("foo");
+        let b = interner.intern_owned("foo".to_string());
+        let c = interner.intern("bar");
+
+        assert!(Rc::ptr_eq(&a, &b));
+        assert!(!Rc::ptr_eq(&a, &c));
+        assert_eq!(InternedKey(a), InternedKey(b));
+    }
+
+    #[test]
+    fn test_keys_compare_handles() {
+        let a = Rc::new(ObjString::new("foo".to_string()));
+        let b = Rc::new(ObjString::new("foo".to_string()));
+
+        assert_ne!(InternedKey(a.clone()), InternedKey(b));
+        assert_eq!(InternedKey(a.clone()), InternedKey(a));
+    }
+
+    #[test]
+    fn test_sweep_forgets_unused_strings() {
+        let mut interner = Interner::new();
+        let kept = interner.intern("kept");
+        interner.intern("dropped");
+        assert_eq!(2, interner.strings.len());
+
+        interner.sweep();
+        assert_eq!(1, interner.strings.len());
+        assert!(Rc::ptr_eq(&kept, &interner.intern("kept")));
+        assert_eq!(
+            std::mem::size_of::<ObjString>() + 2 * "kept".len(),
+            interner.heap_size()
+        );
+    }
+}
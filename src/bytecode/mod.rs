@@ -1,4 +1,8 @@
 pub mod chunk;
 pub mod compiler;
+pub mod gc;
+pub mod interner;
+pub mod natives;
+pub mod object;
 pub mod value;
 pub mod vm;
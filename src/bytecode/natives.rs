@@ -0,0 +1,104 @@
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use super::object::{ObjList, ObjMap};
+use super::value::Value;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Seconds elapsed since the first call to `clock`. Like C's `clock` it's only meaningful as
+/// the difference between two calls.
+pub fn clock(_args: &[Value]) -> Result<Value, String> {
+    let start = START.get_or_init(Instant::now);
+    Ok(Value::Float(start.elapsed().as_secs_f64()))
+}
+
+/// Whether the map has an entry for the key
+pub fn has(args: &[Value]) -> Result<Value, String> {
+    let map = as_map("has", &args[0])?;
+    Ok(Value::Bool(map.contains(&args[1].to_key()?)))
+}
+
+/// Removes the entry for the key, returns whether there was one
+pub fn delete(args: &[Value]) -> Result<Value, String> {
+    let map = as_map("delete", &args[0])?;
+    Ok(Value::Bool(map.remove(&args[1].to_key()?).is_some()))
+}
+
+/// A new list with the keys of the map, in the order they were first inserted
+pub fn keys(args: &[Value]) -> Result<Value, String> {
+    let map = as_map("keys", &args[0])?;
+    Ok(Value::List(Rc::new(ObjList::new(map.keys()))))
+}
+
+/// Number of items in a list or entries in a map
+pub fn len(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::List(list) => Ok(Value::Integer(list.items.borrow().len() as i64)),
+        Value::Map(map) => Ok(Value::Integer(map.len() as i64)),
+        value => Err(format!("len expects a list or a map, got {}", value)),
+    }
+}
+
+fn as_map<'a>(name: &str, value: &'a Value) -> Result<&'a ObjMap, String> {
+    match value {
+        Value::Map(map) => Ok(map),
+        value => Err(format!("{} expects a map, got {}", name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_clock_goes_forward() {
+        let (first, second) = match (clock(&[]), clock(&[])) {
+            (Ok(Value::Float(first)), Ok(Value::Float(second))) => (first, second),
+            values => panic!("Expected two numbers, got {:?}", values),
+        };
+        assert!(first >= 0.0);
+        assert!(second >= first);
+    }
+
+    #[test]
+    fn test_map_natives() {
+        let map = Rc::new(ObjMap::new());
+        map.set(Value::Integer(1).to_key().unwrap(), Value::Nil);
+        map.set(Value::Bool(true).to_key().unwrap(), Value::Nil);
+        let map = Value::Map(map);
+
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            has(&[map.clone(), Value::Integer(1)])
+        );
+        assert_eq!(
+            Ok(Value::Bool(true)),
+            delete(&[map.clone(), Value::Integer(1)])
+        );
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            delete(&[map.clone(), Value::Integer(1)])
+        );
+        assert_eq!(
+            Ok(Value::Bool(false)),
+            has(&[map.clone(), Value::Integer(1)])
+        );
+        assert_eq!(
+            "[true]",
+            keys(std::slice::from_ref(&map)).unwrap().to_string()
+        );
+        assert_eq!(Ok(Value::Integer(1)), len(&[map]));
+
+        assert_eq!(
+            Err("has expects a map, got 1".to_string()),
+            has(&[Value::Integer(1), Value::Nil])
+        );
+        assert_eq!(
+            Err("NaN can't be a map key".to_string()),
+            delete(&[Value::Map(Rc::new(ObjMap::new())), Value::Float(f64::NAN)])
+        );
+    }
+}
@@ -0,0 +1,524 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
+
+use super::chunk::Chunk;
+use super::interner::InternedKey;
+use super::value::{MapKey, Value};
+
+/// Heap allocated string. `Value::String` holds it behind an `Rc`, so copying a string value
+/// around the stack only bumps a reference count.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjString {
+    chars: String,
+}
+
+impl ObjString {
+    pub fn new(chars: String) -> Self {
+        Self { chars }
+    }
+}
+
+impl Display for ObjString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.chars)
+    }
+}
+
+/// Where a closure captures a variable from when it's created: a local slot of the enclosing
+/// function's frame or one of the enclosing closure's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpvalueDescriptor {
+    pub index: u8,
+    pub is_local: bool,
+}
+
+/// A compiled function with its own chunk. The top level script is compiled into a function
+/// without a name.
+pub struct ObjFunction {
+    pub arity: u8,
+    pub chunk: Chunk,
+    pub name: Option<Rc<ObjString>>,
+    pub upvalues: Vec<UpvalueDescriptor>,
+}
+
+impl ObjFunction {
+    pub fn new(name: Option<Rc<ObjString>>) -> Self {
+        Self {
+            arity: 0,
+            chunk: Chunk::new(),
+            name,
+            upvalues: Vec::new(),
+        }
+    }
+
+    pub fn script(chunk: Chunk) -> Self {
+        Self {
+            arity: 0,
+            chunk,
+            name: None,
+            upvalues: Vec::new(),
+        }
+    }
+}
+
+/// Functions are only equal to themselves
+impl PartialEq for ObjFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// A list of values, shared by every value pointing to it
+pub struct ObjList {
+    pub items: RefCell<Vec<Value>>,
+}
+
+impl ObjList {
+    pub fn new(items: Vec<Value>) -> Self {
+        Self {
+            items: RefCell::new(items),
+        }
+    }
+}
+
+/// Lists are only equal to themselves
+impl PartialEq for ObjList {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (i, item) in self.items.borrow().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// A hash map from keys to values that remembers the order keys were first inserted in
+#[derive(Default)]
+pub struct ObjMap {
+    entries: RefCell<MapEntries>,
+}
+
+#[derive(Default)]
+struct MapEntries {
+    index: HashMap<MapKey, usize>,
+    entries: Vec<(MapKey, Value)>,
+}
+
+impl ObjMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<Value> {
+        let map = self.entries.borrow();
+        map.index.get(key).map(|i| map.entries[*i].1.clone())
+    }
+
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.entries.borrow().index.contains_key(key)
+    }
+
+    /// Inserts or replaces the value for `key`, a replaced key keeps its position
+    pub fn set(&self, key: MapKey, value: Value) {
+        let mut map = self.entries.borrow_mut();
+        match map.index.get(&key) {
+            Some(i) => {
+                let i = *i;
+                map.entries[i].1 = value;
+            }
+            None => {
+                let i = map.entries.len();
+                map.index.insert(key.clone(), i);
+                map.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &MapKey) -> Option<Value> {
+        let mut map = self.entries.borrow_mut();
+        let removed = map.index.remove(key)?;
+        let (_, value) = map.entries.remove(removed);
+        for i in map.index.values_mut() {
+            if *i > removed {
+                *i -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn keys(&self) -> Vec<Value> {
+        let map = self.entries.borrow();
+        map.entries.iter().map(|(key, _)| key.to_value()).collect()
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        let map = self.entries.borrow();
+        map.entries.iter().map(|(_, value)| value.clone()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.borrow().entries.len()
+    }
+
+    pub fn clear(&self) {
+        *self.entries.borrow_mut() = MapEntries::default();
+    }
+}
+
+/// Maps are only equal to themselves
+impl PartialEq for ObjMap {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.entries.borrow().entries.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", key, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// A runtime error turned into a value so it can be caught
+pub struct ObjError {
+    pub message: Rc<ObjString>,
+    pub line: usize,
+}
+
+impl ObjError {
+    pub fn new(message: Rc<ObjString>, line: usize) -> Self {
+        Self { message, line }
+    }
+}
+
+/// Errors are only equal to themselves
+impl PartialEq for ObjError {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] Error: {}", self.line, self.message)
+    }
+}
+
+/// A function implemented in Rust. It gets the call arguments and its error is raised as a
+/// runtime error where it was called.
+pub type NativeFn = fn(&[Value]) -> Result<Value, String>;
+
+pub struct ObjNative {
+    pub name: Rc<ObjString>,
+    pub arity: u8,
+    pub function: NativeFn,
+}
+
+impl ObjNative {
+    pub fn new(name: Rc<ObjString>, arity: u8, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+/// Natives are only equal to themselves
+impl PartialEq for ObjNative {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// A captured variable. It points to a stack slot while the variable is alive on the stack
+/// and holds the value itself once it's closed. Closures capturing the same variable share it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjUpvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A function along with the variables it captured. Every function is wrapped in a closure
+/// at runtime.
+/// `module` is the module whose globals the closure reads and writes, closures of the main
+/// script use the vm globals.
+pub struct ObjClosure {
+    pub function: Rc<ObjFunction>,
+    pub upvalues: Vec<Rc<RefCell<ObjUpvalue>>>,
+    pub module: Option<Rc<ObjModule>>,
+}
+
+impl ObjClosure {
+    pub fn new(
+        function: Rc<ObjFunction>,
+        upvalues: Vec<Rc<RefCell<ObjUpvalue>>>,
+        module: Option<Rc<ObjModule>>,
+    ) -> Self {
+        Self {
+            function,
+            upvalues,
+            module,
+        }
+    }
+}
+
+/// Closures are only equal to themselves
+impl PartialEq for ObjClosure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+/// An imported file. Its globals are exported as the properties of the module value.
+pub struct ObjModule {
+    pub name: Rc<ObjString>,
+    pub globals: RefCell<HashMap<InternedKey, Value>>,
+}
+
+impl ObjModule {
+    pub fn new(name: Rc<ObjString>) -> Self {
+        Self {
+            name,
+            globals: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+/// Modules are only equal to themselves
+impl PartialEq for ObjModule {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+/// A class and its methods, which are added one by one after the class is created
+pub struct ObjClass {
+    pub name: Rc<ObjString>,
+    pub methods: RefCell<HashMap<InternedKey, Rc<ObjClosure>>>,
+}
+
+impl ObjClass {
+    pub fn new(name: Rc<ObjString>) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn find_method(&self, name: &InternedKey) -> Option<Rc<ObjClosure>> {
+        self.methods.borrow().get(name).cloned()
+    }
+}
+
+/// Classes are only equal to themselves
+impl PartialEq for ObjClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// An instance of a class. Fields can be added to it at any time.
+pub struct ObjInstance {
+    pub class: Rc<ObjClass>,
+    pub fields: RefCell<HashMap<InternedKey, Value>>,
+}
+
+impl ObjInstance {
+    pub fn new(class: Rc<ObjClass>) -> Self {
+        Self {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+/// Instances are only equal to themselves
+impl PartialEq for ObjInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class)
+    }
+}
+
+/// A method taken out of an instance, which remembers the instance it was taken from so
+/// `this` is bound to it when called.
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: Rc<ObjClosure>,
+}
+
+impl ObjBoundMethod {
+    pub fn new(receiver: Value, method: Rc<ObjClosure>) -> Self {
+        Self { receiver, method }
+    }
+}
+
+/// Bound methods are only equal to themselves
+impl PartialEq for ObjBoundMethod {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Debug for ObjBoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Display for ObjBoundMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_display() {
+        let a = ObjString::new("foo bar".to_string());
+        assert_eq!("foo bar", a.to_string());
+    }
+
+    #[test]
+    fn test_function_display() {
+        let name = Rc::new(ObjString::new("foo".to_string()));
+        assert_eq!("<fn foo>", ObjFunction::new(Some(name)).to_string());
+        assert_eq!("<script>", ObjFunction::script(Chunk::new()).to_string());
+    }
+
+    #[test]
+    fn test_class_display() {
+        let class = Rc::new(ObjClass::new(Rc::new(ObjString::new("Foo".to_string()))));
+        assert_eq!("Foo", class.to_string());
+        assert_eq!("Foo instance", ObjInstance::new(class).to_string());
+    }
+
+    #[test]
+    fn test_list_display() {
+        let inner = Value::List(Rc::new(ObjList::new(vec![Value::Nil])));
+        let list = ObjList::new(vec![Value::Integer(1), Value::Bool(true), inner]);
+        assert_eq!("[1, true, [nil]]", list.to_string());
+    }
+
+    #[test]
+    fn test_functions_are_only_equal_to_themselves() {
+        let a = ObjFunction::new(None);
+        let b = ObjFunction::new(None);
+        assert!(a == a);
+        assert!(a != b);
+    }
+}
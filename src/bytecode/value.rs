@@ -1,20 +1,226 @@
 use std::fmt::Display;
+use std::rc::Rc;
 
+use super::object::{
+    ObjBoundMethod, ObjClass, ObjClosure, ObjError, ObjFunction, ObjInstance, ObjList, ObjMap,
+    ObjModule, ObjNative, ObjString,
+};
 use crate::errors::ReloxError;
 use crate::Result;
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum Value {
     Bool(bool),
-    Number(f32),
+    Integer(i64),
+    Float(f64),
+    String(Rc<ObjString>),
+    Function(Rc<ObjFunction>),
+    Closure(Rc<ObjClosure>),
+    Class(Rc<ObjClass>),
+    Instance(Rc<ObjInstance>),
+    BoundMethod(Rc<ObjBoundMethod>),
+    Native(Rc<ObjNative>),
+    List(Rc<ObjList>),
+    Map(Rc<ObjMap>),
+    Error(Rc<ObjError>),
+    Module(Rc<ObjModule>),
+    #[default]
     Nil,
 }
 
+impl Value {
+    /// Lox truthiness: only `nil` and `false` are falsey
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Lox equality: values of different types are never equal, so `nil == false` is false.
+    /// Integers and floats are both numbers, so `1 == 1.0` is true. Strings are interned, so
+    /// they're compared by handle.
+    pub fn equals(self, rhs: Self) -> Result<Value> {
+        let equal = match (&self, &rhs) {
+            (Self::String(lhs), Self::String(rhs)) => Rc::ptr_eq(lhs, rhs),
+            _ => match self.clone().numbers(rhs.clone()) {
+                Some(Numbers::Integers(lhs, rhs)) => lhs == rhs,
+                Some(Numbers::Floats(lhs, rhs)) => lhs == rhs,
+                None => self == rhs,
+            },
+        };
+        Ok(Value::Bool(equal))
+    }
+
+    /// The key a value is stored under in a map. Only nil, booleans, numbers and strings can
+    /// be keys. Whole floats share the key of the equal integer, so `-0.0` and `0` are the same
+    /// key. NaN, which isn't equal to itself, is rejected.
+    pub fn to_key(&self) -> std::result::Result<MapKey, String> {
+        match self {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(val) => Ok(MapKey::Bool(*val)),
+            Value::Integer(val) => Ok(MapKey::Integer(*val)),
+            Value::Float(val) if val.is_nan() => Err("NaN can't be a map key".to_string()),
+            Value::Float(val) => match as_integer(*val) {
+                Some(val) => Ok(MapKey::Integer(val)),
+                None => Ok(MapKey::Float(val.to_bits())),
+            },
+            Value::String(val) => Ok(MapKey::String(Rc::clone(val))),
+            _ => Err(format!(
+                "Map keys must be nil, booleans, numbers or strings, got {}",
+                self
+            )),
+        }
+    }
+
+    pub fn greater(self, rhs: Self) -> Result<Value> {
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(lhs, rhs)) => Ok(Self::Bool(lhs > rhs)),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Bool(lhs > rhs)),
+            None => ReloxError::new_fatal_error("Tried to compare oranges with apples".to_string()),
+        }
+    }
+
+    pub fn less(self, rhs: Self) -> Result<Value> {
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(lhs, rhs)) => Ok(Self::Bool(lhs < rhs)),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Bool(lhs < rhs)),
+            None => ReloxError::new_fatal_error("Tried to compare oranges with apples".to_string()),
+        }
+    }
+
+    /// Integer powers are checked. A negative exponent gives a float, `2 ** -1` is `0.5`.
+    pub fn power(self, rhs: Self) -> Result<Value> {
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(base, exp)) if exp < 0 => {
+                Ok(Self::Float((base as f64).powf(exp as f64)))
+            }
+            Some(Numbers::Integers(base, exp)) => {
+                // Exponents past u32::MAX only fit for the bases 0, 1 and -1, keeping the
+                // parity is enough for them
+                let exp = if exp > u32::MAX as i64 {
+                    u32::MAX - 1 + (exp % 2) as u32
+                } else {
+                    exp as u32
+                };
+                base.checked_pow(exp)
+                    .map_or_else(integer_overflow, |val| Ok(Self::Integer(val)))
+            }
+            Some(Numbers::Floats(base, exp)) => Ok(Self::Float(base.powf(exp))),
+            None => {
+                ReloxError::new_fatal_error("Tried to exponentiate oranges with apples".to_string())
+            }
+        }
+    }
+
+    /// Flips the bits of an integer
+    pub fn bit_not(self) -> Result<Value> {
+        match self {
+            Self::Integer(val) => Ok(Self::Integer(!val)),
+            _ => ReloxError::new_fatal_error(format!(
+                "Operand of '~' must be an integer, got {}",
+                self
+            )),
+        }
+    }
+
+    /// Bitwise operators only work on integers
+    fn integers(self, rhs: Self, operator: &str) -> Result<(i64, i64)> {
+        match (&self, &rhs) {
+            (Self::Integer(lhs), Self::Integer(rhs)) => Ok((*lhs, *rhs)),
+            _ => ReloxError::new_fatal_error(format!(
+                "Operands of '{}' must be integers, got {} and {}",
+                operator, self, rhs
+            )),
+        }
+    }
+
+    /// Promotes a pair of numbers to a common type: two integers stay integers, an integer
+    /// with a float becomes a float. `None` when either isn't a number.
+    fn numbers(self, rhs: Self) -> Option<Numbers> {
+        match (self, rhs) {
+            (Self::Integer(lhs), Self::Integer(rhs)) => Some(Numbers::Integers(lhs, rhs)),
+            (Self::Integer(lhs), Self::Float(rhs)) => Some(Numbers::Floats(lhs as f64, rhs)),
+            (Self::Float(lhs), Self::Integer(rhs)) => Some(Numbers::Floats(lhs, rhs as f64)),
+            (Self::Float(lhs), Self::Float(rhs)) => Some(Numbers::Floats(lhs, rhs)),
+            _ => None,
+        }
+    }
+}
+
+enum Numbers {
+    Integers(i64, i64),
+    Floats(f64, f64),
+}
+
+/// The integer equal to the float, if there's one
+fn as_integer(val: f64) -> Option<i64> {
+    // i64::MAX as f64 rounds up to 2^63, which doesn't fit
+    if val.fract() == 0.0 && val >= i64::MIN as f64 && val < i64::MAX as f64 {
+        Some(val as i64)
+    } else {
+        None
+    }
+}
+
+fn integer_overflow<T>() -> Result<T> {
+    ReloxError::new_fatal_error("Integer overflow".to_string())
+}
+
+fn shift_amount(amount: i64) -> Result<u32> {
+    if (0..64).contains(&amount) {
+        Ok(amount as u32)
+    } else {
+        ReloxError::new_fatal_error(format!(
+            "Shift amount must be between 0 and 63, got {}",
+            amount
+        ))
+    }
+}
+
+/// Hashable form of the values that can be map keys. Strings are compared by content.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Float(u64),
+    String(Rc<ObjString>),
+}
+
+impl MapKey {
+    pub fn to_value(&self) -> Value {
+        match self {
+            MapKey::Nil => Value::Nil,
+            MapKey::Bool(val) => Value::Bool(*val),
+            MapKey::Integer(val) => Value::Integer(*val),
+            MapKey::Float(bits) => Value::Float(f64::from_bits(*bits)),
+            MapKey::String(val) => Value::String(Rc::clone(val)),
+        }
+    }
+}
+
+impl Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_value())
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Number(val) => write!(f, "{}", val),
+            Value::Integer(val) => write!(f, "{}", val),
+            // Debug keeps the fraction of whole floats, 1.0 isn't shown as the integer 1
+            Value::Float(val) => write!(f, "{:?}", val),
             Value::Bool(val) => write!(f, "{}", val),
+            Value::String(val) => write!(f, "{}", val),
+            Value::Function(val) => write!(f, "{}", val),
+            Value::Closure(val) => write!(f, "{}", val),
+            Value::Class(val) => write!(f, "{}", val),
+            Value::Instance(val) => write!(f, "{}", val),
+            Value::BoundMethod(val) => write!(f, "{}", val),
+            Value::Native(val) => write!(f, "{}", val),
+            Value::List(val) => write!(f, "{}", val),
+            Value::Map(val) => write!(f, "{}", val),
+            Value::Error(val) => write!(f, "{}", val),
+            Value::Module(val) => write!(f, "{}", val),
             Value::Nil => write!(f, "nil"),
         }
     }
@@ -24,9 +230,12 @@ impl std::ops::Add for Value {
     type Output = Result<Value>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Ok(Self::Number(lhs + rhs)),
-            _ => ReloxError::new_fatal_error("Tried to add oranges with apples".to_string()),
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(lhs, rhs)) => lhs
+                .checked_add(rhs)
+                .map_or_else(integer_overflow, |val| Ok(Self::Integer(val))),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Float(lhs + rhs)),
+            None => ReloxError::new_fatal_error("Tried to add oranges with apples".to_string()),
         }
     }
 }
@@ -35,9 +244,14 @@ impl std::ops::Sub for Value {
     type Output = Result<Value>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Ok(Self::Number(lhs - rhs)),
-            _ => ReloxError::new_fatal_error("Tried to substract oranges with apples".to_string()),
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(lhs, rhs)) => lhs
+                .checked_sub(rhs)
+                .map_or_else(integer_overflow, |val| Ok(Self::Integer(val))),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Float(lhs - rhs)),
+            None => {
+                ReloxError::new_fatal_error("Tried to substract oranges with apples".to_string())
+            }
         }
     }
 }
@@ -46,37 +260,229 @@ impl std::ops::Mul for Value {
     type Output = Result<Value>;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Ok(Self::Number(lhs * rhs)),
-            _ => ReloxError::new_fatal_error("Tried to multiply oranges with apples".to_string()),
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(lhs, rhs)) => lhs
+                .checked_mul(rhs)
+                .map_or_else(integer_overflow, |val| Ok(Self::Integer(val))),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Float(lhs * rhs)),
+            None => {
+                ReloxError::new_fatal_error("Tried to multiply oranges with apples".to_string())
+            }
         }
     }
 }
 
+/// Integer division truncates towards zero. Floats follow IEEE 754, so dividing by zero gives
+/// an infinity or NaN.
 impl std::ops::Div for Value {
     type Output = Result<Value>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Self::Number(lhs), Self::Number(rhs)) => Ok(Self::Number(lhs / rhs)),
-            _ => ReloxError::new_fatal_error("Tried to divide oranges with apples".to_string()),
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(_, 0)) => {
+                ReloxError::new_fatal_error("Integer division by zero".to_string())
+            }
+            Some(Numbers::Integers(lhs, rhs)) => lhs
+                .checked_div(rhs)
+                .map_or_else(integer_overflow, |val| Ok(Self::Integer(val))),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Float(lhs / rhs)),
+            None => ReloxError::new_fatal_error("Tried to divide oranges with apples".to_string()),
         }
     }
 }
 
+/// The remainder has the sign of the dividend, so `a == a / b * b + a % b` for integers
+impl std::ops::Rem for Value {
+    type Output = Result<Value>;
+
+    fn rem(self, rhs: Self) -> Self::Output {
+        match self.numbers(rhs) {
+            Some(Numbers::Integers(_, 0)) => {
+                ReloxError::new_fatal_error("Integer modulo by zero".to_string())
+            }
+            Some(Numbers::Integers(lhs, rhs)) => lhs
+                .checked_rem(rhs)
+                .map_or_else(integer_overflow, |val| Ok(Self::Integer(val))),
+            Some(Numbers::Floats(lhs, rhs)) => Ok(Self::Float(lhs % rhs)),
+            None => ReloxError::new_fatal_error("Tried to modulo oranges with apples".to_string()),
+        }
+    }
+}
+
+impl std::ops::BitAnd for Value {
+    type Output = Result<Value>;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        let (lhs, rhs) = self.integers(rhs, "&")?;
+        Ok(Self::Integer(lhs & rhs))
+    }
+}
+
+impl std::ops::BitOr for Value {
+    type Output = Result<Value>;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        let (lhs, rhs) = self.integers(rhs, "|")?;
+        Ok(Self::Integer(lhs | rhs))
+    }
+}
+
+impl std::ops::BitXor for Value {
+    type Output = Result<Value>;
+
+    fn bitxor(self, rhs: Self) -> Self::Output {
+        let (lhs, rhs) = self.integers(rhs, "^")?;
+        Ok(Self::Integer(lhs ^ rhs))
+    }
+}
+
+/// The bits shifted out are dropped, `1 << 63` is the smallest integer
+impl std::ops::Shl for Value {
+    type Output = Result<Value>;
+
+    fn shl(self, rhs: Self) -> Self::Output {
+        let (lhs, rhs) = self.integers(rhs, "<<")?;
+        Ok(Self::Integer(lhs << shift_amount(rhs)?))
+    }
+}
+
+/// Shifting right keeps the sign
+impl std::ops::Shr for Value {
+    type Output = Result<Value>;
+
+    fn shr(self, rhs: Self) -> Self::Output {
+        let (lhs, rhs) = self.integers(rhs, ">>")?;
+        Ok(Self::Integer(lhs >> shift_amount(rhs)?))
+    }
+}
+
 impl std::ops::Neg for Value {
     type Output = Result<Value>;
 
     fn neg(self) -> Self::Output {
         match self {
-            Self::Number(val) => Ok(Self::Number(-val)),
+            Self::Integer(val) => val
+                .checked_neg()
+                .map_or_else(integer_overflow, |val| Ok(Self::Integer(val))),
+            Self::Float(val) => Ok(Self::Float(-val)),
             _ => ReloxError::new_fatal_error("Tried to negate unegable(?)".to_string()),
         }
     }
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Value::Nil
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::interner::Interner;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_equality_across_types() {
+        assert_eq!(
+            Value::Bool(false),
+            Value::Nil.equals(Value::Bool(false)).unwrap()
+        );
+        assert_eq!(
+            Value::Bool(false),
+            Value::Float(0.0).equals(Value::Nil).unwrap()
+        );
+        assert_eq!(Value::Bool(true), Value::Nil.equals(Value::Nil).unwrap());
+        assert_eq!(
+            Value::Bool(true),
+            Value::Float(2.0).equals(Value::Float(2.0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_strings_are_compared_by_handle() {
+        let mut interner = Interner::new();
+        let foo = Value::String(interner.intern("foo"));
+        let bar = Value::String(interner.intern("bar"));
+
+        assert_eq!("foo", foo.to_string());
+        assert_eq!(
+            Value::Bool(true),
+            foo.clone()
+                .equals(Value::String(interner.intern("foo")))
+                .unwrap()
+        );
+        assert_eq!(Value::Bool(false), foo.clone().equals(bar).unwrap());
+
+        let not_interned = Value::String(Rc::new(ObjString::new("foo".to_string())));
+        assert_eq!(
+            Value::Bool(false),
+            foo.clone().equals(not_interned).unwrap()
+        );
+        assert!((foo.clone() + Value::Float(1.0)).is_err());
+        assert!(foo.less(Value::Nil).is_err());
+    }
+
+    #[test]
+    fn test_map_keys() {
+        let mut interner = Interner::new();
+        assert_eq!(Value::Float(0.0).to_key(), Value::Float(-0.0).to_key());
+        assert_eq!(
+            Value::String(interner.intern("a")).to_key(),
+            Value::String(Rc::new(ObjString::new("a".to_string()))).to_key()
+        );
+        assert_ne!(Value::Nil.to_key(), Value::Bool(false).to_key());
+        assert_eq!(
+            Err("NaN can't be a map key".to_string()),
+            Value::Float(f64::NAN).to_key()
+        );
+        assert!(Value::List(Rc::new(ObjList::new(vec![]))).to_key().is_err());
+
+        let key = Value::Float(1.5).to_key().unwrap();
+        assert_eq!(Value::Float(1.5), key.to_value());
+    }
+
+    #[test]
+    fn test_integers_and_floats() {
+        assert_eq!(
+            Value::Integer(3),
+            (Value::Integer(1) + Value::Integer(2)).unwrap()
+        );
+        assert_eq!(
+            Value::Float(3.5),
+            (Value::Integer(1) + Value::Float(2.5)).unwrap()
+        );
+        assert_eq!(
+            Value::Integer(-3),
+            (Value::Integer(-7) / Value::Integer(2)).unwrap()
+        );
+        assert_eq!(
+            Value::Bool(true),
+            Value::Integer(1).equals(Value::Float(1.0)).unwrap()
+        );
+        assert_eq!(
+            Value::Bool(true),
+            Value::Integer(2).less(Value::Float(2.5)).unwrap()
+        );
+        assert_eq!(Value::Integer(1).to_key(), Value::Float(1.0).to_key());
+        assert_ne!(Value::Float(1.5).to_key(), Value::Integer(1).to_key());
+        assert_eq!("1.0", Value::Float(1.0).to_string());
+
+        assert!((Value::Integer(i64::MAX) + Value::Integer(1)).is_err());
+        assert!((Value::Integer(i64::MIN) / Value::Integer(-1)).is_err());
+        assert!((-Value::Integer(i64::MIN)).is_err());
+        assert!((Value::Integer(1) / Value::Integer(0)).is_err());
+        assert_eq!(
+            Value::Float(f64::INFINITY),
+            (Value::Integer(1) / Value::Float(0.0)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ordering_needs_numbers() {
+        assert_eq!(
+            Value::Bool(true),
+            Value::Float(2.0).greater(Value::Float(1.0)).unwrap()
+        );
+        assert_eq!(
+            Value::Bool(false),
+            Value::Float(2.0).less(Value::Float(1.0)).unwrap()
+        );
+        assert!(Value::Nil.less(Value::Float(1.0)).is_err());
+        assert!(Value::Bool(true).greater(Value::Bool(false)).is_err());
     }
 }
@@ -1,29 +1,44 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
 use crate::{
     bytecode::chunk::OpCode,
-    errors::{ErrorKind::StackOverFlow, ErrorKind::VmError, ReloxError},
+    errors::{ErrorKind::StackOverFlow, ErrorKind::VmError, FatalError, ReloxError, RuntimeError},
 };
 
-use super::{chunk::Chunk, value::Value};
+use super::gc::{Heap, HeapObject, Marker};
+use super::interner::{InternedKey, Interner};
+use super::natives;
+use super::object::{
+    NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjError, ObjFunction, ObjInstance, ObjList,
+    ObjMap, ObjModule, ObjNative, ObjString, ObjUpvalue,
+};
+use super::{
+    chunk::Chunk,
+    value::{MapKey, Value},
+};
 use crate::Result;
 
-const STACK_MAX: usize = 256;
+const FRAMES_MAX: usize = 64;
+const STACK_MAX: usize = FRAMES_MAX * 256;
 
 #[derive(Debug)]
 struct VmStack<T> {
-    stack: [T; STACK_MAX],
+    stack: Box<[T]>,
     stack_top: usize,
 }
 
-impl<T: Default + Copy> VmStack<T> {
+impl<T: Default + Clone> VmStack<T> {
     pub fn new() -> Self {
         Self {
-            stack: [T::default(); STACK_MAX],
+            stack: vec![T::default(); STACK_MAX].into_boxed_slice(),
             stack_top: 0,
         }
     }
 
     pub fn push(&mut self, val: T) -> Result<()> {
-        if self.stack_top >= 256 {
+        if self.stack_top >= STACK_MAX {
             return ReloxError::new_runtime_error(
                 0,
                 "StackOverflow bro".to_string(),
@@ -43,105 +58,920 @@ impl<T: Default + Copy> VmStack<T> {
         }
 
         self.stack_top -= 1;
-        let val = self.stack[self.stack_top];
+        let val = std::mem::take(&mut self.stack[self.stack_top]);
         Ok(val)
     }
 
+    pub fn peek(&self, distance: usize) -> Result<T> {
+        if distance >= self.stack_top {
+            return ReloxError::new_fatal_error(
+                "Tried to peek invalid index from instruction stack".to_string(),
+            );
+        }
+
+        Ok(self.stack[self.stack_top - 1 - distance].clone())
+    }
+
+    pub fn get(&self, index: usize) -> Result<T> {
+        if index >= self.stack_top {
+            return ReloxError::new_fatal_error(
+                "Tried to get invalid index from instruction stack".to_string(),
+            );
+        }
+
+        Ok(self.stack[index].clone())
+    }
+
+    pub fn set(&mut self, index: usize, val: T) -> Result<()> {
+        if index >= self.stack_top {
+            return ReloxError::new_fatal_error(
+                "Tried to set invalid index from instruction stack".to_string(),
+            );
+        }
+
+        self.stack[index] = val;
+        Ok(())
+    }
+
+    /// Drops every value above `len`
+    pub fn truncate(&mut self, len: usize) {
+        while self.stack_top > len {
+            self.stack_top -= 1;
+            self.stack[self.stack_top] = T::default();
+        }
+    }
+
+    /// Empties the stack leaving only `base` in the first slot
+    pub fn reset_with(&mut self, base: T) {
+        self.truncate(0);
+        self.stack[0] = base;
+        self.stack_top = 1;
+    }
+
     pub fn stack_slice(&self, from: usize, to: usize) -> &[T] {
         &self.stack[from..to]
     }
 }
 
+/// An ongoing function call. `slot_base` is where the function's stack window starts, its
+/// slot 0 holds the closure itself followed by the arguments.
 #[derive(Debug)]
-pub struct Vm {
-    chunk: Chunk,
+struct CallFrame {
+    closure: Rc<ObjClosure>,
     ip: usize,
+    slot_base: usize,
+}
+
+#[derive(Debug)]
+pub struct Vm {
+    frames: Vec<CallFrame>,
     value_stack: VmStack<Value>,
+    open_upvalues: Vec<Rc<RefCell<ObjUpvalue>>>,
+    init_string: InternedKey,
+    globals: HashMap<InternedKey, Value>,
+    natives: HashMap<InternedKey, Value>,
+    modules: HashMap<InternedKey, Rc<ObjModule>>,
+    interner: Interner,
+    heap: Heap,
     debug_mode: bool,
 }
 
 impl Vm {
-    pub fn run_with(chunk: Chunk, debug_mode: bool) -> Result<Value> {
-        Self::new(chunk, debug_mode).run()
+    #[cfg(test)]
+    pub fn run_with(chunk: Chunk, interner: Interner, debug_mode: bool) -> Result<Value> {
+        Self::new(chunk, interner, debug_mode).run()
     }
 
-    pub fn new(chunk: Chunk, debug_mode: bool) -> Self {
-        Self {
-            chunk,
+    /// Creates a vm for a chunk. The interner must be the one the chunk was compiled with.
+    pub fn new(chunk: Chunk, mut interner: Interner, debug_mode: bool) -> Self {
+        let init_string = InternedKey(interner.intern("init"));
+        let mut vm = Self {
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            init_string,
             debug_mode,
-            ip: 0,
             value_stack: VmStack::new(),
+            globals: HashMap::new(),
+            natives: HashMap::new(),
+            modules: HashMap::new(),
+            interner,
+            heap: Heap::new(),
+        };
+        vm.load(chunk);
+        vm.define_native("clock", 0, natives::clock);
+        vm.define_native("has", 2, natives::has);
+        vm.define_native("delete", 2, natives::delete);
+        vm.define_native("keys", 1, natives::keys);
+        vm.define_native("len", 1, natives::len);
+        vm
+    }
+
+    /// Registers a Rust function as a global callable from scripts with `arity` arguments.
+    /// Natives are visible from every module.
+    pub fn define_native(&mut self, name: &str, arity: u8, function: NativeFn) {
+        let name = InternedKey(self.interner.intern(name));
+        let native = Value::Native(Rc::new(ObjNative::new(Rc::clone(&name.0), arity, function)));
+        self.globals.insert(name.clone(), native.clone());
+        self.natives.insert(name, native);
+    }
+
+    /// Collects garbage on every allocation instead of when the heap grows, to shake out
+    /// objects the collector misses.
+    pub fn stress_gc(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    /// The interner to compile the chunks given to `interpret` with
+    pub fn interner(&mut self) -> &mut Interner {
+        &mut self.interner
+    }
+
+    /// Runs a new chunk on this vm, keeping the globals defined by the previous ones.
+    pub fn interpret(&mut self, chunk: Chunk) -> Result<Value> {
+        self.load(chunk);
+        self.run()
+    }
+
+    /// Wraps the chunk in a script closure and sets up its frame. Upvalues left open by a
+    /// previous run that failed are closed first.
+    fn load(&mut self, chunk: Chunk) {
+        if self.debug_mode {
+            chunk.dissasemble();
         }
+
+        let function = Rc::new(ObjFunction::script(chunk));
+        let script = self.new_closure(ObjClosure::new(function, Vec::new(), None));
+
+        self.close_upvalues(0);
+        self.value_stack
+            .reset_with(Value::Closure(Rc::clone(&script)));
+        self.frames.clear();
+        self.frames.push(CallFrame {
+            closure: script,
+            ip: 0,
+            slot_base: 0,
+        });
     }
 
+    /// Runs until the script returns. Runtime errors are thrown as error values, so they can
+    /// be caught by the script, and only end the execution when nothing catches them.
     pub fn run(&mut self) -> Result<Value> {
         loop {
-            if let Some(instruction) = self.chunk.instruction_at(self.ip) {
-                self.ip += 1;
-
-                if self.debug_mode {
-                    println!("== Current stack ==");
-                    println!("{:?}", &self.value_stack.stack_slice(0, self.ip + 1));
-                    self.chunk.dissasemble_instruction(instruction, 0, &mut 0);
-                }
-
-                match instruction {
-                    OpCode::Constant { constant_offset } => {
-                        let the_constant =
-                            self.chunk.read_constant(*constant_offset).ok_or_else(|| {
-                                ReloxError::new_unwrapped_fatal_error(
-                                    "Constant not set".to_string(),
-                                )
-                            })?;
-                        self.value_stack.push(*the_constant)?;
+            let error = match self.execute() {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let exception = match &error {
+                ReloxError::RuntimeError(RuntimeError {
+                    line,
+                    message,
+                    kind: VmError,
+                }) => ObjError::new(self.interner.intern(message), *line),
+                _ => return Err(error),
+            };
+
+            if !self.throw(Value::Error(Rc::new(exception)))? {
+                return Err(error);
+            }
+        }
+    }
+
+    fn execute(&mut self) -> Result<Value> {
+        loop {
+            // Collections happen between instructions, when every live value is reachable
+            // from the roots
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+
+            let instruction = self.read_instruction()?;
+
+            if self.debug_mode {
+                println!("== Current stack ==");
+                println!(
+                    "{:?}",
+                    &self.value_stack.stack_slice(0, self.value_stack.stack_top)
+                );
+                let frame = self.frame()?;
+                frame.closure.function.chunk.dissasemble_instruction(
+                    &instruction,
+                    frame.ip - 1,
+                    &mut 0,
+                );
+            }
+
+            match instruction {
+                OpCode::Constant { constant_offset } => {
+                    let the_constant = self
+                        .chunk()?
+                        .read_constant(constant_offset)
+                        .cloned()
+                        .ok_or_else(|| {
+                            ReloxError::new_unwrapped_fatal_error("Constant not set".to_string())
+                        })?;
+                    self.value_stack.push(the_constant)?;
+                }
+                OpCode::Negate => {
+                    let value = self.value_stack.pop()?;
+                    match -value {
+                        Ok(neg_value) => self.value_stack.push(neg_value)?,
+                        Err(error) => return self.value_error(error),
+                    };
+                }
+                OpCode::Add => self.add()?,
+                OpCode::Substract => self.binary_op(std::ops::Sub::sub)?,
+                OpCode::Divide => self.binary_op(std::ops::Div::div)?,
+                OpCode::Multiply => self.binary_op(std::ops::Mul::mul)?,
+                OpCode::Modulo => self.binary_op(std::ops::Rem::rem)?,
+                OpCode::Power => self.binary_op(Value::power)?,
+                OpCode::BitAnd => self.binary_op(std::ops::BitAnd::bitand)?,
+                OpCode::BitOr => self.binary_op(std::ops::BitOr::bitor)?,
+                OpCode::BitXor => self.binary_op(std::ops::BitXor::bitxor)?,
+                OpCode::ShiftLeft => self.binary_op(std::ops::Shl::shl)?,
+                OpCode::ShiftRight => self.binary_op(std::ops::Shr::shr)?,
+                OpCode::BitNot => {
+                    let value = self.value_stack.pop()?;
+                    match value.bit_not() {
+                        Ok(flipped) => self.value_stack.push(flipped)?,
+                        Err(error) => return self.value_error(error),
+                    };
+                }
+                OpCode::Equal => self.binary_op(Value::equals)?,
+                OpCode::Greater => self.binary_op(Value::greater)?,
+                OpCode::Less => self.binary_op(Value::less)?,
+                OpCode::Return => {
+                    let value = self.value_stack.pop()?;
+                    let frame = self.frames.pop().ok_or_else(|| {
+                        ReloxError::new_unwrapped_fatal_error(
+                            "Returned without a frame".to_string(),
+                        )
+                    })?;
+
+                    self.close_upvalues(frame.slot_base);
+                    if self.frames.is_empty() {
+                        self.value_stack.truncate(0);
+                        return Ok(value);
                     }
-                    OpCode::Negate => {
-                        let value = self.value_stack.pop()?;
-                        match -value {
-                            Ok(neg_value) => self.value_stack.push(neg_value)?,
-                            Err(error_msg) => {
-                                let line_num = self.chunk.line_at(self.ip - 1);
-                                return ReloxError::new_runtime_error(
-                                    line_num as usize,
-                                    error_msg.to_string(),
-                                    VmError,
-                                );
+
+                    self.value_stack.truncate(frame.slot_base);
+                    self.value_stack.push(value)?;
+                }
+                OpCode::Nil => self.value_stack.push(Value::Nil)?,
+                OpCode::True => self.value_stack.push(Value::Bool(true))?,
+                OpCode::False => self.value_stack.push(Value::Bool(false))?,
+                OpCode::Not => {
+                    let value = self.value_stack.pop()?;
+                    self.value_stack.push(Value::Bool(value.is_falsey()))?;
+                }
+                OpCode::Pop => {
+                    self.value_stack.pop()?;
+                }
+                OpCode::Print => {
+                    let value = self.value_stack.pop()?;
+                    println!("{}", value);
+                }
+                OpCode::DefineGlobal { constant_offset } => {
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+                    let value = self.value_stack.pop()?;
+                    match self.frame()?.closure.module.clone() {
+                        Some(module) => module.globals.borrow_mut().insert(name, value),
+                        None => self.globals.insert(name, value),
+                    };
+                }
+                OpCode::GetGlobal { constant_offset } => {
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+                    let value = match &self.frame()?.closure.module {
+                        Some(module) => module.globals.borrow().get(&name).cloned(),
+                        None => self.globals.get(&name).cloned(),
+                    };
+                    match value.or_else(|| self.natives.get(&name).cloned()) {
+                        Some(value) => self.value_stack.push(value)?,
+                        None => {
+                            return self.runtime_error(format!("Undefined variable '{}'", name.0))
+                        }
+                    }
+                }
+                OpCode::SetGlobal { constant_offset } => {
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+                    let value = self.value_stack.peek(0)?;
+                    let assigned = match self.frame()?.closure.module.clone() {
+                        Some(module) => match module.globals.borrow_mut().get_mut(&name) {
+                            Some(global) => {
+                                *global = value;
+                                true
                             }
-                        };
+                            None => false,
+                        },
+                        None => match self.globals.get_mut(&name) {
+                            Some(global) => {
+                                *global = value;
+                                true
+                            }
+                            None => false,
+                        },
+                    };
+                    if !assigned {
+                        return self.runtime_error(format!("Undefined variable '{}'", name.0));
                     }
-                    OpCode::Add => self.binary_op(std::ops::Add::add)?,
-                    OpCode::Substract => self.binary_op(std::ops::Sub::sub)?,
-                    OpCode::Divide => self.binary_op(std::ops::Div::div)?,
-                    OpCode::Multiply => self.binary_op(std::ops::Mul::mul)?,
-                    OpCode::Return => {
-                        let value = self.value_stack.pop()?;
-                        return Ok(value);
+                }
+                OpCode::GetLocal { slot } => {
+                    let slot_base = self.frame()?.slot_base;
+                    let value = self.value_stack.get(slot_base + slot as usize)?;
+                    self.value_stack.push(value)?;
+                }
+                OpCode::SetLocal { slot } => {
+                    let slot_base = self.frame()?.slot_base;
+                    let value = self.value_stack.peek(0)?;
+                    self.value_stack.set(slot_base + slot as usize, value)?;
+                }
+                OpCode::Jump { offset } => self.frame_mut()?.ip += offset as usize,
+                OpCode::Loop { offset } => self.frame_mut()?.ip -= offset as usize,
+                OpCode::JumpIfFalse { offset } => {
+                    if self.value_stack.peek(0)?.is_falsey() {
+                        self.frame_mut()?.ip += offset as usize;
                     }
-                    OpCode::Nil => self.value_stack.push(Value::Nil)?,
-                    OpCode::True => self.value_stack.push(Value::Bool(true))?,
-                    OpCode::False => self.value_stack.push(Value::Bool(false))?,
-                    OpCode::Not => {
-                        if let Ok(Value::Bool(value)) = self.value_stack.pop() {
-                            self.value_stack.push(Value::Bool(!value))?;
+                }
+                OpCode::Call { arg_count } => {
+                    let callee = self.value_stack.peek(arg_count as usize)?;
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::Closure { constant_offset } => {
+                    let function = match self.chunk()?.read_constant(constant_offset) {
+                        Some(Value::Function(function)) => Rc::clone(function),
+                        _ => return ReloxError::new_fatal_error("Function not set".to_string()),
+                    };
+
+                    let slot_base = self.frame()?.slot_base;
+                    let mut upvalues = Vec::with_capacity(function.upvalues.len());
+                    for descriptor in function.upvalues.iter() {
+                        let upvalue = if descriptor.is_local {
+                            self.capture_upvalue(slot_base + descriptor.index as usize)
                         } else {
-                            let line_num = self.chunk.line_at(self.ip - 1);
-                            return ReloxError::new_runtime_error(
-                                line_num as usize,
-                                "Invalid type, you can only negate booleans".to_string(),
-                                VmError,
-                            );
+                            self.upvalue(descriptor.index)?
+                        };
+                        upvalues.push(upvalue);
+                    }
+
+                    let module = self.frame()?.closure.module.clone();
+                    let closure = self.new_closure(ObjClosure::new(function, upvalues, module));
+                    self.value_stack.push(Value::Closure(closure))?;
+                }
+                OpCode::GetUpvalue { slot } => {
+                    let value = match &*self.upvalue(slot)?.borrow() {
+                        ObjUpvalue::Open(index) => self.value_stack.get(*index)?,
+                        ObjUpvalue::Closed(value) => value.clone(),
+                    };
+                    self.value_stack.push(value)?;
+                }
+                OpCode::SetUpvalue { slot } => {
+                    let value = self.value_stack.peek(0)?;
+                    match &mut *self.upvalue(slot)?.borrow_mut() {
+                        ObjUpvalue::Open(index) => self.value_stack.set(*index, value)?,
+                        ObjUpvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.value_stack.stack_top - 1);
+                    self.value_stack.pop()?;
+                }
+                OpCode::Class { constant_offset } => {
+                    let name = self.read_identifier(constant_offset)?;
+                    let class = Rc::new(ObjClass::new(name));
+                    self.heap.track(HeapObject::Class(Rc::downgrade(&class)));
+                    self.value_stack.push(Value::Class(class))?;
+                }
+                OpCode::Method { constant_offset } => {
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+                    match (self.value_stack.peek(1)?, self.value_stack.pop()?) {
+                        (Value::Class(class), Value::Closure(method)) => {
+                            class.methods.borrow_mut().insert(name, method);
                         }
+                        _ => return ReloxError::new_fatal_error("Method not set".to_string()),
                     }
-                };
-            } else {
-                return ReloxError::new_fatal_error(format!(
-                    "Read wrong instruction, stacktrace: {:?}",
-                    &self.value_stack.stack_slice(0, self.ip + 1)
-                ));
+                }
+                OpCode::GetProperty { constant_offset } => {
+                    let instance = match self.value_stack.peek(0)? {
+                        Value::Instance(instance) => instance,
+                        Value::Module(module) => {
+                            let name = InternedKey(self.read_identifier(constant_offset)?);
+                            let member = module.globals.borrow().get(&name).cloned();
+                            let value = match member {
+                                Some(value) => value,
+                                None => {
+                                    return self.runtime_error(format!(
+                                        "Undefined property '{}' of {}",
+                                        name.0, module
+                                    ))
+                                }
+                            };
+                            self.value_stack.pop()?;
+                            self.value_stack.push(value)?;
+                            continue;
+                        }
+                        Value::Error(error) => {
+                            let name = self.read_identifier(constant_offset)?;
+                            let value = match name.to_string().as_str() {
+                                "message" => Value::String(Rc::clone(&error.message)),
+                                "line" => Value::Integer(error.line as i64),
+                                _ => {
+                                    return self
+                                        .runtime_error(format!("Undefined property '{}'", name))
+                                }
+                            };
+                            self.value_stack.pop()?;
+                            self.value_stack.push(value)?;
+                            continue;
+                        }
+                        _ => {
+                            return self.runtime_error("Only instances have properties".to_string())
+                        }
+                    };
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+
+                    let field = instance.fields.borrow().get(&name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => {
+                            let receiver = Value::Instance(Rc::clone(&instance));
+                            self.bind_method(&instance.class, receiver, &name)?
+                        }
+                    };
+                    self.value_stack.pop()?;
+                    self.value_stack.push(value)?;
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.value_stack.peek(1)? {
+                        Value::Class(superclass) => superclass,
+                        _ => return self.runtime_error("Superclass must be a class".to_string()),
+                    };
+
+                    match self.value_stack.pop()? {
+                        Value::Class(subclass) => {
+                            let methods = superclass.methods.borrow().clone();
+                            subclass.methods.borrow_mut().extend(methods);
+                        }
+                        _ => return ReloxError::new_fatal_error("Subclass not set".to_string()),
+                    }
+                }
+                OpCode::GetSuper { constant_offset } => {
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+                    let superclass = match self.value_stack.pop()? {
+                        Value::Class(superclass) => superclass,
+                        _ => return ReloxError::new_fatal_error("Superclass not set".to_string()),
+                    };
+
+                    let receiver = self.value_stack.pop()?;
+                    let method = self.bind_method(&superclass, receiver, &name)?;
+                    self.value_stack.push(method)?;
+                }
+                OpCode::SetProperty { constant_offset } => {
+                    let instance = match self.value_stack.peek(1)? {
+                        Value::Instance(instance) => instance,
+                        _ => return self.runtime_error("Only instances have fields".to_string()),
+                    };
+                    let name = InternedKey(self.read_identifier(constant_offset)?);
+
+                    let value = self.value_stack.pop()?;
+                    instance.fields.borrow_mut().insert(name, value.clone());
+                    self.value_stack.pop()?;
+                    self.value_stack.push(value)?;
+                }
+                OpCode::BuildList { item_count } => {
+                    let stack_top = self.value_stack.stack_top;
+                    let first = stack_top - item_count as usize;
+                    let items = self.value_stack.stack_slice(first, stack_top).to_vec();
+                    self.value_stack.truncate(first);
+
+                    let list = Rc::new(ObjList::new(items));
+                    self.heap.track(HeapObject::List(Rc::downgrade(&list)));
+                    self.value_stack.push(Value::List(list))?;
+                }
+                OpCode::Throw => {
+                    let exception = self.value_stack.pop()?;
+                    if !self.throw(exception.clone())? {
+                        return self.uncaught(exception);
+                    }
+                }
+                OpCode::ToString => {
+                    if !matches!(self.value_stack.peek(0)?, Value::String(_)) {
+                        let chars = self.value_stack.pop()?.to_string();
+                        self.heap
+                            .allocated(std::mem::size_of::<ObjString>() + chars.len());
+                        let string = self.interner.intern_owned(chars);
+                        self.value_stack.push(Value::String(string))?;
+                    }
+                }
+                OpCode::Import { constant_offset } => {
+                    let function = match self.chunk()?.read_constant(constant_offset) {
+                        Some(Value::Function(function)) => Rc::clone(function),
+                        _ => return ReloxError::new_fatal_error("Module not set".to_string()),
+                    };
+                    let path = function.name.clone().ok_or_else(|| {
+                        ReloxError::new_unwrapped_fatal_error("Module without a path".to_string())
+                    })?;
+
+                    // Modules run once, later imports get the same module
+                    if let Some(module) = self.modules.get(&InternedKey(Rc::clone(&path))) {
+                        let module = Value::Module(Rc::clone(module));
+                        self.value_stack.push(module)?;
+                        continue;
+                    }
+
+                    let module = Rc::new(ObjModule::new(Rc::clone(&path)));
+                    self.modules.insert(InternedKey(path), Rc::clone(&module));
+                    let closure =
+                        self.new_closure(ObjClosure::new(function, Vec::new(), Some(module)));
+                    self.value_stack.push(Value::Closure(Rc::clone(&closure)))?;
+                    self.call(closure, 0)?;
+                }
+                OpCode::CurrentModule => {
+                    let module = match &self.frame()?.closure.module {
+                        Some(module) => Value::Module(Rc::clone(module)),
+                        None => Value::Nil,
+                    };
+                    self.value_stack.push(module)?;
+                }
+                OpCode::BuildMap { entry_count } => {
+                    let stack_top = self.value_stack.stack_top;
+                    let first = stack_top - 2 * entry_count as usize;
+                    let map = Rc::new(ObjMap::new());
+                    for entry in self.value_stack.stack_slice(first, stack_top).chunks(2) {
+                        map.set(self.map_key(&entry[0])?, entry[1].clone());
+                    }
+                    self.value_stack.truncate(first);
+
+                    self.heap.track(HeapObject::Map(Rc::downgrade(&map)));
+                    self.value_stack.push(Value::Map(map))?;
+                }
+                OpCode::GetIndex => {
+                    let index = self.value_stack.pop()?;
+                    let item = match self.value_stack.pop()? {
+                        Value::List(list) => {
+                            let items = list.items.borrow();
+                            let index = self.list_index(index, items.len())?;
+                            items[index].clone()
+                        }
+                        Value::Map(map) => {
+                            let key = self.map_key(&index)?;
+                            match map.get(&key) {
+                                Some(value) => value,
+                                None => {
+                                    return self.runtime_error(format!("Undefined key '{}'", key))
+                                }
+                            }
+                        }
+                        _ => {
+                            return self.runtime_error("Can only index lists and maps".to_string())
+                        }
+                    };
+                    self.value_stack.push(item)?;
+                }
+                OpCode::SetIndex => {
+                    let value = self.value_stack.pop()?;
+                    let index = self.value_stack.pop()?;
+                    match self.value_stack.pop()? {
+                        Value::List(list) => {
+                            let index = self.list_index(index, list.items.borrow().len())?;
+                            list.items.borrow_mut()[index] = value.clone();
+                        }
+                        Value::Map(map) => map.set(self.map_key(&index)?, value.clone()),
+                        _ => {
+                            return self.runtime_error("Can only index lists and maps".to_string())
+                        }
+                    }
+                    self.value_stack.push(value)?;
+                }
+            };
+        }
+    }
+
+    fn frame(&self) -> Result<&CallFrame> {
+        self.frames
+            .last()
+            .ok_or_else(|| ReloxError::new_unwrapped_fatal_error("No frame to run".to_string()))
+    }
+
+    fn frame_mut(&mut self) -> Result<&mut CallFrame> {
+        self.frames
+            .last_mut()
+            .ok_or_else(|| ReloxError::new_unwrapped_fatal_error("No frame to run".to_string()))
+    }
+
+    fn chunk(&self) -> Result<&Chunk> {
+        Ok(&self.frame()?.closure.function.chunk)
+    }
+
+    fn read_instruction(&mut self) -> Result<OpCode> {
+        let frame = self.frame_mut()?;
+        let instruction = frame
+            .closure
+            .function
+            .chunk
+            .instruction_at(frame.ip)
+            .copied();
+        frame.ip += 1;
+
+        instruction.ok_or_else(|| {
+            ReloxError::new_unwrapped_fatal_error(format!(
+                "Read wrong instruction, stacktrace: {:?}",
+                &self.value_stack.stack_slice(0, self.value_stack.stack_top)
+            ))
+        })
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: u8) -> Result<()> {
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::Native(native) => self.call_native(&native, arg_count),
+            Value::BoundMethod(bound) => {
+                let callee_slot = self.value_stack.stack_top - arg_count as usize - 1;
+                self.value_stack.set(callee_slot, bound.receiver.clone())?;
+                self.call(Rc::clone(&bound.method), arg_count)
+            }
+            Value::Class(class) => {
+                let callee_slot = self.value_stack.stack_top - arg_count as usize - 1;
+                let instance = Rc::new(ObjInstance::new(Rc::clone(&class)));
+                self.heap
+                    .track(HeapObject::Instance(Rc::downgrade(&instance)));
+                self.value_stack
+                    .set(callee_slot, Value::Instance(instance))?;
+
+                match class.find_method(&self.init_string) {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        self.runtime_error(format!("Expected 0 arguments but got {}", arg_count))
+                    }
+                    None => Ok(()),
+                }
+            }
+            _ => self.runtime_error("Can only call functions and classes".to_string()),
+        }
+    }
+
+    /// Pushes a frame for the closure, whose window starts at the callee below the arguments
+    fn call(&mut self, closure: Rc<ObjClosure>, arg_count: u8) -> Result<()> {
+        let arity = closure.function.arity;
+        if arg_count != arity {
+            return self.runtime_error(format!(
+                "Expected {} arguments but got {}",
+                arity, arg_count
+            ));
+        }
+
+        if self.frames.len() >= FRAMES_MAX {
+            return self.runtime_error("Stack overflow".to_string());
+        }
+
+        let slot_base = self.value_stack.stack_top - arg_count as usize - 1;
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slot_base,
+        });
+        Ok(())
+    }
+
+    /// Calls the native with the arguments on top of the stack, replacing them and the callee
+    /// with its result.
+    fn call_native(&mut self, native: &ObjNative, arg_count: u8) -> Result<()> {
+        if arg_count != native.arity {
+            return self.runtime_error(format!(
+                "Expected {} arguments but got {}",
+                native.arity, arg_count
+            ));
+        }
+
+        let callee_slot = self.value_stack.stack_top - arg_count as usize - 1;
+        let args = self
+            .value_stack
+            .stack_slice(callee_slot + 1, self.value_stack.stack_top);
+
+        match (native.function)(args) {
+            Ok(result) => {
+                // Natives can't reach the heap, the lists and maps they build are tracked here
+                match &result {
+                    Value::List(list) => self.heap.track(HeapObject::List(Rc::downgrade(list))),
+                    Value::Map(map) => self.heap.track(HeapObject::Map(Rc::downgrade(map))),
+                    _ => {}
+                }
+
+                self.value_stack.truncate(callee_slot);
+                self.value_stack.push(result)
+            }
+            Err(message) => self.runtime_error(message),
+        }
+    }
+
+    /// Looks the method up in the class and binds it to the receiver
+    fn bind_method(
+        &mut self,
+        class: &Rc<ObjClass>,
+        receiver: Value,
+        name: &InternedKey,
+    ) -> Result<Value> {
+        match class.find_method(name) {
+            Some(method) => {
+                let bound = Rc::new(ObjBoundMethod::new(receiver, method));
+                self.heap
+                    .track(HeapObject::BoundMethod(Rc::downgrade(&bound)));
+                Ok(Value::BoundMethod(bound))
             }
+            None => self.runtime_error(format!("Undefined property '{}'", name.0)),
+        }
+    }
+
+    fn upvalue(&self, slot: u8) -> Result<Rc<RefCell<ObjUpvalue>>> {
+        match self.frame()?.closure.upvalues.get(slot as usize) {
+            Some(upvalue) => Ok(Rc::clone(upvalue)),
+            None => ReloxError::new_fatal_error("Upvalue not set".to_string()),
+        }
+    }
+
+    /// Returns the open upvalue for the stack slot, creating it if no closure captured the
+    /// slot yet, so every closure capturing a variable shares the same upvalue.
+    fn capture_upvalue(&mut self, index: usize) -> Rc<RefCell<ObjUpvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| *upvalue.borrow() == ObjUpvalue::Open(index));
+
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = Rc::new(RefCell::new(ObjUpvalue::Open(index)));
+        self.heap
+            .track(HeapObject::Upvalue(Rc::downgrade(&upvalue)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves the values of the upvalues pointing at or above the stack slot into the upvalues
+    /// themselves, so they outlive the slots.
+    fn close_upvalues(&mut self, last: usize) {
+        let value_stack = &self.value_stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                ObjUpvalue::Open(index) if index >= last => {
+                    *upvalue = ObjUpvalue::Closed(value_stack.stack[index].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn read_identifier(&self, constant_offset: u8) -> Result<Rc<ObjString>> {
+        match self.chunk()?.read_constant(constant_offset) {
+            Some(Value::String(name)) => Ok(Rc::clone(name)),
+            _ => ReloxError::new_fatal_error("Identifier not set".to_string()),
+        }
+    }
+
+    /// Adds two numbers or concatenates two strings. The concatenation is interned so it can
+    /// be compared by handle like any other string.
+    fn add(&mut self) -> Result<()> {
+        match (self.value_stack.peek(1)?, self.value_stack.peek(0)?) {
+            (Value::String(lhs), Value::String(rhs)) => {
+                self.value_stack.pop()?;
+                self.value_stack.pop()?;
+                let chars = format!("{}{}", lhs, rhs);
+                self.heap
+                    .allocated(std::mem::size_of::<ObjString>() + chars.len());
+                let concatenated = self.interner.intern_owned(chars);
+                self.value_stack.push(Value::String(concatenated))
+            }
+            _ => self.binary_op(std::ops::Add::add),
+        }
+    }
+
+    fn map_key(&self, value: &Value) -> Result<MapKey> {
+        value
+            .to_key()
+            .or_else(|message| self.runtime_error(message))
+    }
+
+    /// Checks the index is a whole number within the bounds of the list
+    fn list_index(&self, index: Value, len: usize) -> Result<usize> {
+        let index = match index {
+            Value::Integer(index) => index,
+            Value::Float(index) if index.fract() == 0.0 => index as i64,
+            Value::Float(index) => {
+                return self
+                    .runtime_error(format!("List index must be a whole number, got {}", index))
+            }
+            _ => return self.runtime_error("List index must be a number".to_string()),
+        };
+
+        if index >= 0 && (index as usize) < len {
+            Ok(index as usize)
+        } else {
+            self.runtime_error(format!(
+                "Index {} out of range for a list of length {}",
+                index, len
+            ))
         }
     }
 
+    fn new_closure(&mut self, closure: ObjClosure) -> Rc<ObjClosure> {
+        let closure = Rc::new(closure);
+        self.heap
+            .track(HeapObject::Closure(Rc::downgrade(&closure)));
+        closure
+    }
+
+    /// Marks the objects reachable from the stack, the globals, the call frames and the open
+    /// upvalues, then frees the unreachable ones along with the strings nothing uses.
+    fn collect_garbage(&mut self) {
+        let before = self.heap.bytes_allocated();
+        let mut marker = Marker::new();
+
+        for value in self.value_stack.stack_slice(0, self.value_stack.stack_top) {
+            marker.mark_value(value);
+        }
+        for value in self.globals.values() {
+            marker.mark_value(value);
+        }
+        for frame in self.frames.iter() {
+            marker.mark_value(&Value::Closure(Rc::clone(&frame.closure)));
+        }
+        for upvalue in self.open_upvalues.iter() {
+            marker.mark_upvalue(upvalue);
+        }
+        for module in self.modules.values() {
+            marker.mark_value(&Value::Module(Rc::clone(module)));
+        }
+        marker.trace();
+
+        self.heap.sweep(&marker);
+        self.interner.sweep();
+        self.heap.adjust_threshold(self.interner.heap_size());
+
+        if self.debug_mode {
+            println!(
+                "== GC collected {} bytes, {} left ==",
+                before.saturating_sub(self.heap.bytes_allocated()),
+                self.heap.bytes_allocated()
+            );
+        }
+    }
+
+    /// Unwinds to the innermost handler around the current instruction of any frame, then
+    /// jumps to it with the exception on the stack. Nothing changes when there's no handler.
+    fn throw(&mut self, exception: Value) -> Result<bool> {
+        for frame_index in (0..self.frames.len()).rev() {
+            let frame = &self.frames[frame_index];
+            let handler = frame
+                .closure
+                .function
+                .chunk
+                .handler_for(frame.ip.saturating_sub(1))
+                .copied();
+
+            if let Some(handler) = handler {
+                let stack_top = frame.slot_base + handler.stack_depth;
+                self.frames.truncate(frame_index + 1);
+                self.close_upvalues(stack_top);
+                self.value_stack.truncate(stack_top);
+                self.value_stack.push(exception)?;
+                self.frame_mut()?.ip = handler.target;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The error ending the execution when nothing catches a thrown value. Errors keep the
+    /// line they were created at.
+    fn uncaught<T>(&self, exception: Value) -> Result<T> {
+        match exception {
+            Value::Error(error) => {
+                ReloxError::new_runtime_error(error.line, error.message.to_string(), VmError)
+            }
+            exception => self.runtime_error(format!("Uncaught exception: {}", exception)),
+        }
+    }
+
+    /// Operations on values report type mismatches as fatal errors, they're runtime errors of
+    /// the script
+    fn value_error<T>(&self, error: ReloxError) -> Result<T> {
+        match error {
+            ReloxError::FatalError(FatalError { message }) => self.runtime_error(message),
+            error => self.runtime_error(error.to_string()),
+        }
+    }
+
+    fn runtime_error<T>(&self, message: String) -> Result<T> {
+        let line_num = match self.frames.last() {
+            Some(frame) => frame.closure.function.chunk.line_at(frame.ip - 1),
+            None => 0,
+        };
+        ReloxError::new_runtime_error(line_num as usize, message, VmError)
+    }
+
     fn binary_op<F>(&mut self, mut op: F) -> Result<()>
     where
         F: FnMut(Value, Value) -> Result<Value>,
@@ -150,10 +980,7 @@ impl Vm {
         let y = self.value_stack.pop()?;
         match op(y, x) {
             Ok(value) => self.value_stack.push(value),
-            Err(error_msg) => {
-                let line_num = self.chunk.line_at(self.ip - 1);
-                ReloxError::new_runtime_error(line_num as usize, error_msg.to_string(), VmError)
-            }
+            Err(error) => self.value_error(error),
         }
     }
 }
@@ -161,84 +988,91 @@ impl Vm {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bytecode::compiler::Compiler;
+    use crate::scanner::Scanner;
     use pretty_assertions::assert_eq;
 
+    fn global(vm: &mut Vm, name: &str) -> Option<Value> {
+        let name = InternedKey(vm.interner.intern(name));
+        vm.globals.get(&name).cloned()
+    }
+
     #[test]
     fn test_vm_stack() {
         let mut stack = VmStack::<Value>::new();
         assert!(stack.pop().is_err());
-        stack.push(Value::Number(63.2)).unwrap();
-        stack.push(Value::Number(6.2)).unwrap();
-        assert_eq!(stack.pop().unwrap(), Value::Number(6.2));
-        assert_eq!(stack.pop().unwrap(), Value::Number(63.2));
+        stack.push(Value::Float(63.2)).unwrap();
+        stack.push(Value::Float(6.2)).unwrap();
+        assert_eq!(stack.pop().unwrap(), Value::Float(6.2));
+        assert_eq!(stack.pop().unwrap(), Value::Float(63.2));
         assert!(stack.pop().is_err());
     }
 
     #[test]
     fn test_negation() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(3.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(3), 0).unwrap();
         chunk.write_bytecode(OpCode::Negate, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().unwrap(), Value::Number(-3.0));
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Integer(-3));
     }
 
     #[test]
     fn test_addition() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(3.0), 0).unwrap();
-        chunk.add_constant(Value::Number(2.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(3), 0).unwrap();
+        chunk.add_constant(Value::Integer(2), 0).unwrap();
         chunk.write_bytecode(OpCode::Add, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().unwrap(), Value::Number(5.0));
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Integer(5));
     }
 
     #[test]
     fn test_subsraction() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(3.0), 0).unwrap();
-        chunk.add_constant(Value::Number(2.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(3), 0).unwrap();
+        chunk.add_constant(Value::Integer(2), 0).unwrap();
         chunk.write_bytecode(OpCode::Substract, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().unwrap(), Value::Number(1.0));
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Integer(1));
     }
 
     #[test]
     fn test_division() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(6.0), 0).unwrap();
-        chunk.add_constant(Value::Number(2.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(6), 0).unwrap();
+        chunk.add_constant(Value::Integer(2), 0).unwrap();
         chunk.write_bytecode(OpCode::Divide, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().unwrap(), Value::Number(3.0));
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Integer(3));
     }
 
     #[test]
     fn test_mult() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(3.0), 0).unwrap();
-        chunk.add_constant(Value::Number(2.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(3), 0).unwrap();
+        chunk.add_constant(Value::Integer(2), 0).unwrap();
         chunk.write_bytecode(OpCode::Multiply, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().unwrap(), Value::Number(6.0));
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Integer(6));
     }
 
     #[test]
     fn test_add_mult() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(1.0), 0).unwrap();
-        chunk.add_constant(Value::Number(2.0), 0).unwrap();
-        chunk.add_constant(Value::Number(3.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(1), 0).unwrap();
+        chunk.add_constant(Value::Integer(2), 0).unwrap();
+        chunk.add_constant(Value::Integer(3), 0).unwrap();
         chunk.write_bytecode(OpCode::Multiply, 0);
         chunk.write_bytecode(OpCode::Add, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().unwrap(), Value::Number(7.0));
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Integer(7));
     }
 
     #[test]
@@ -247,7 +1081,7 @@ mod tests {
         chunk.write_bytecode(OpCode::True, 0);
         chunk.write_bytecode(OpCode::Not, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
+        let mut vm = Vm::new(chunk, Interner::new(), false);
         assert_eq!(vm.run().unwrap(), Value::Bool(false));
     }
 
@@ -258,17 +1092,350 @@ mod tests {
         chunk.write_bytecode(OpCode::Not, 0);
         chunk.write_bytecode(OpCode::Not, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
+        let mut vm = Vm::new(chunk, Interner::new(), false);
         assert_eq!(vm.run().unwrap(), Value::Bool(true));
     }
 
     #[test]
     fn test_noting_a_not_boolean() {
         let mut chunk = Chunk::new();
-        chunk.add_constant(Value::Number(3.0), 0).unwrap();
+        chunk.add_constant(Value::Integer(3), 0).unwrap();
         chunk.write_bytecode(OpCode::Not, 0);
         chunk.write_bytecode(OpCode::Return, 0);
-        let mut vm = Vm::new(chunk, false);
-        assert_eq!(vm.run().is_err(), true);
+        let mut vm = Vm::new(chunk, Interner::new(), false);
+        assert_eq!(vm.run().unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_loops_dont_grow_the_stack() {
+        let source = "var i = 0; var running = true; var other = true;
+            while (running) { var a = 1; { var b = a; i = i + b; }
+              if (other) other = false; else running = false; }
+            for (var j = true; j; j = false) { var c = 2; i = i + c; }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        assert_eq!(vm.run().unwrap(), Value::Nil);
+        assert_eq!(0, vm.value_stack.stack_top);
+        assert_eq!(Some(Value::Integer(4)), global(&mut vm, "i"));
+    }
+
+    #[test]
+    fn test_long_loops() {
+        let source =
+            "var sum = 0; for (var i = 0; i < 10000; i = i + 1) { var j = i; sum = sum + 1; }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        assert_eq!(vm.run().unwrap(), Value::Nil);
+        assert_eq!(0, vm.value_stack.stack_top);
+        assert_eq!(Some(Value::Integer(10000)), global(&mut vm, "sum"));
+    }
+
+    #[test]
+    fn test_comparison_type_errors() {
+        let tokens = Scanner::run_with("1 < 2;\nnil > 1;".to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let error = Vm::run_with(chunk, interner, false).expect_err("should've been an error");
+        assert_eq!(VmError, error.kind());
+        assert_eq!(
+            "[line 2] RuntimeError VmError: Tried to compare oranges with apples",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        let mut interner = Interner::new();
+        let mut chunk = Chunk::new();
+        chunk
+            .add_constant(Value::String(interner.intern("a")), 0)
+            .unwrap();
+        chunk
+            .add_constant(Value::String(interner.intern("b")), 0)
+            .unwrap();
+        chunk.write_bytecode(OpCode::Add, 0);
+        chunk.write_bytecode(OpCode::Return, 0);
+        let ab = interner.intern("ab");
+        let mut vm = Vm::new(chunk, interner, false);
+
+        match vm.run().unwrap() {
+            Value::String(result) => assert!(Rc::ptr_eq(&ab, &result)),
+            value => panic!("Expected a string, got {}", value),
+        }
+    }
+
+    #[test]
+    fn test_strings_built_at_runtime_are_interned() {
+        let source = "var a = \"foo\"; var b = \"fo\" + \"o\"; a == b";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        assert_eq!(
+            Vm::run_with(chunk, interner, false).unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_calls_leave_the_stack_clean() {
+        let source = "fun sum(a, b) { var c = a + b; { var d = c; return d; } }
+            var total = 0;
+            for (var i = 0; i < 100; i = i + 1) { total = sum(total, i); }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        assert_eq!(vm.run().unwrap(), Value::Nil);
+        assert_eq!(0, vm.value_stack.stack_top);
+        assert!(vm.frames.is_empty());
+        assert_eq!(Some(Value::Integer(4950)), global(&mut vm, "total"));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let tokens =
+            Scanner::run_with("fun forever() { forever(); }\nforever();".to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let error = Vm::run_with(chunk, interner, false).expect_err("should've been an error");
+        assert_eq!(VmError, error.kind());
+        assert_eq!(
+            "[line 1] RuntimeError VmError: Stack overflow",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_interpret_resets_frames_after_errors() {
+        let tokens = Scanner::run_with("fun f() { return nil + 1; } f();".to_string()).unwrap();
+        let mut vm = Vm::new(Chunk::new(), Interner::new(), false);
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        assert!(vm.interpret(chunk).is_err());
+
+        let tokens = Scanner::run_with("1 + 2".to_string()).unwrap();
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        assert_eq!(vm.interpret(chunk).unwrap(), Value::Integer(3));
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn test_captured_variables_outlive_their_frame() {
+        let source = "fun make() { var a = 1; var b = 2; fun sum() { return a + b; } return sum; }
+            var sum = make();
+            var result = sum();";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        assert_eq!(vm.run().unwrap(), Value::Nil);
+        assert_eq!(0, vm.value_stack.stack_top);
+        assert!(vm.open_upvalues.is_empty());
+        assert_eq!(Some(Value::Integer(3)), global(&mut vm, "result"));
+    }
+
+    fn sum(args: &[Value]) -> Result<Value, String> {
+        match args {
+            [Value::Integer(a), Value::Integer(b)] => Ok(Value::Integer(a + b)),
+            _ => Err("Can only sum numbers".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_natives() {
+        let source = "var start = clock(); sum(sum(1, 2), 3) + 0 * start";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut vm = Vm::new(Chunk::new(), Interner::new(), false);
+        vm.define_native("sum", 2, sum);
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        assert_eq!(vm.interpret(chunk).unwrap(), Value::Float(6.0));
+        assert_eq!(0, vm.value_stack.stack_top);
+
+        let tokens = Scanner::run_with("sum".to_string()).unwrap();
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        assert_eq!("<native fn sum>", vm.interpret(chunk).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_native_errors() {
+        let sources = [
+            ("var a = 1;\nsum(1, nil);", "Can only sum numbers"),
+            ("var a = 1;\nsum(1);", "Expected 2 arguments but got 1"),
+        ];
+
+        for (source, expected) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let mut vm = Vm::new(Chunk::new(), Interner::new(), false);
+            vm.define_native("sum", 2, sum);
+            let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+            let error = vm.interpret(chunk).expect_err("should've been an error");
+            assert_eq!(VmError, error.kind());
+            assert_eq!(
+                format!("[line 2] RuntimeError VmError: {}", expected),
+                error.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_gc_frees_unreachable_cycles() {
+        let source = "class Node {}
+            fun link() { var a = Node(); var b = Node(); a.other = b; b.other = a; }
+            for (var i = 0; i < 10; i = i + 1) link();
+            var kept = Node(); kept.self = kept;";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        vm.run().unwrap();
+        assert!(vm.heap.live_objects() > 20);
+
+        vm.collect_garbage();
+        // The script closure is gone by now, only the class, link and the kept instance are left
+        assert_eq!(3, vm.heap.live_objects());
+        match global(&mut vm, "kept") {
+            Some(Value::Instance(kept)) => assert_eq!(1, kept.fields.borrow().len()),
+            value => panic!("Expected an instance, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_gc_frees_unused_strings() {
+        let source = "var a = \"a\"; for (var i = 0; i < 10; i = i + 1) a = a + \"a\";";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        vm.run().unwrap();
+
+        let before = vm.interner.heap_size();
+        vm.collect_garbage();
+        assert!(vm.interner.heap_size() < before);
+        assert_eq!(
+            Some(Value::String(vm.interner.intern("aaaaaaaaaaa"))),
+            global(&mut vm, "a")
+        );
+    }
+
+    #[test]
+    fn test_stress_gc_keeps_reachable_objects() {
+        let source = "class Counter {
+              init() { this.count = 0; }
+              increment() { this.count = this.count + 1; return this.count; }
+            }
+            fun make() {
+              var counter = Counter();
+              var name = \"count\" + \"er\";
+              fun next() { if (name == \"counter\") return counter.increment(); return nil; }
+              return next;
+            }
+            var next = make();
+            next(); next();
+            var cycle = Counter(); cycle.other = Counter(); cycle.other.other = cycle;
+            next() + cycle.other.other.increment()";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        vm.stress_gc(true);
+
+        assert_eq!(vm.run().unwrap(), Value::Integer(4));
+    }
+
+    #[test]
+    fn test_gc_frees_cyclic_lists() {
+        let source = "for (var i = 0; i < 10; i = i + 1) { var xs = [i, nil]; xs[1] = xs; }
+            var kept = [1, nil]; kept[1] = [kept];";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        vm.run().unwrap();
+        assert!(vm.heap.live_objects() >= 12);
+
+        vm.collect_garbage();
+        assert_eq!(2, vm.heap.live_objects());
+
+        vm.stress_gc(true);
+        let source = "kept[1][0][0] + kept[1][0][1][0][0]";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        assert_eq!(vm.interpret(chunk).unwrap(), Value::Integer(2));
+    }
+
+    #[test]
+    fn test_gc_frees_cyclic_maps() {
+        let source = "for (var i = 0; i < 10; i = i + 1) { var m = {\"i\": i}; m[\"self\"] = [m]; }
+            var kept = {\"k\": 1}; kept[\"keys\"] = keys(kept); kept[\"keys\"][0] = kept;";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+        vm.run().unwrap();
+
+        vm.collect_garbage();
+        assert_eq!(2, vm.heap.live_objects());
+
+        vm.stress_gc(true);
+        let source = "len(kept[\"keys\"][0][\"keys\"])";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        assert_eq!(vm.interpret(chunk).unwrap(), Value::Integer(1));
+    }
+
+    #[test]
+    fn test_gc_keeps_values_returned_to_the_host() {
+        let mut vm = Vm::new(Chunk::new(), Interner::new(), false);
+        vm.stress_gc(true);
+        let source = "class Node {} var node = Node(); node.self = node; node.items = [[1], 2];
+            [[1], 2, node]";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        let kept = vm.interpret(chunk).unwrap();
+
+        let source = "node = nil; for (var i = 0; i < 10; i = i + 1) { var xs = [i]; xs[0] = xs; }";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let chunk = Compiler::run_with(tokens, vm.interner()).unwrap();
+        vm.interpret(chunk).unwrap();
+        vm.collect_garbage();
+
+        assert_eq!("[[1], 2, Node instance]", kept.to_string());
+        let items = InternedKey(vm.interner.intern("items"));
+        match &kept {
+            Value::List(list) => match &list.items.borrow()[2] {
+                Value::Instance(node) => {
+                    assert_eq!(2, node.fields.borrow().len());
+                    assert_eq!("[[1], 2]", node.fields.borrow()[&items].to_string());
+                }
+                value => panic!("Expected an instance, got {:?}", value),
+            },
+            value => panic!("Expected a list, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_unwinding_restores_the_stack_and_closes_upvalues() {
+        let source = "var get;
+            fun fail() {
+              var captured = \"kept\";
+              fun inner() { return captured; }
+              get = inner;
+              return [1, 2, nil + 1];
+            }
+            var before = 1;
+            try { var a = 1; fail(); } catch (e) { var b = e; }
+            get()";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let mut interner = Interner::new();
+        let chunk = Compiler::run_with(tokens, &mut interner).unwrap();
+        let mut vm = Vm::new(chunk, interner, false);
+
+        assert_eq!(vm.run().unwrap().to_string(), "kept");
+        assert_eq!(0, vm.value_stack.stack_top);
+        assert!(vm.frames.is_empty());
+        assert!(vm.open_upvalues.is_empty());
     }
 }
@@ -33,6 +33,7 @@ pub struct FatalError {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ReloxError {
     CompilationError(CompilationError),
     RuntimeError(RuntimeError),
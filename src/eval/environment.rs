@@ -0,0 +1,117 @@
+use crate::errors::ErrorKind;
+use crate::errors::ReloxError;
+use crate::eval::interpreted_eval::EvalResult;
+use crate::token::Token;
+use crate::Result;
+use std::collections::HashMap;
+
+/// Environment
+///
+/// Chain of scopes used by the tree-walk interpreter. The first scope holds the globals and
+/// every block pushes a new one that is dropped when the block ends. Lookups walk the chain
+/// from the innermost scope outwards.
+#[derive(Debug)]
+pub struct Environment {
+    scopes: Vec<HashMap<String, EvalResult>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    pub fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn end_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// Defines a variable in the innermost scope, shadowing any outer one with the same name.
+    pub fn define(&mut self, name: &str, value: EvalResult) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<EvalResult> {
+        match self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.lexeme))
+        {
+            Some(value) => Ok(value.clone()),
+            None => undefined_variable(name),
+        }
+    }
+
+    pub fn assign(&mut self, name: &Token, value: EvalResult) -> Result<()> {
+        match self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(&name.lexeme))
+        {
+            Some(variable) => {
+                *variable = value;
+                Ok(())
+            }
+            None => undefined_variable(name),
+        }
+    }
+}
+
+fn undefined_variable<T>(name: &Token) -> Result<T> {
+    ReloxError::new_runtime_error(
+        name.line,
+        format!("Undefined variable '{}'", name.lexeme),
+        ErrorKind::EvalError,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::token_type::TokenType;
+
+    fn identifier(name: &str) -> Token {
+        Token::new(TokenType::Identifier, name, None, 3)
+    }
+
+    #[test]
+    fn test_scope_chain() {
+        let mut env = Environment::new();
+        env.define("a", EvalResult::Float(1.0));
+        env.begin_scope();
+        env.define("a", EvalResult::Float(2.0));
+        env.define("b", EvalResult::Bool(true));
+        assert_eq!(EvalResult::Float(2.0), env.get(&identifier("a")).unwrap());
+
+        env.end_scope();
+        assert_eq!(EvalResult::Float(1.0), env.get(&identifier("a")).unwrap());
+        assert!(env.get(&identifier("b")).is_err());
+    }
+
+    #[test]
+    fn test_assign_walks_the_chain() {
+        let mut env = Environment::new();
+        env.define("a", EvalResult::Float(1.0));
+        env.begin_scope();
+        env.assign(&identifier("a"), EvalResult::Nil).unwrap();
+        env.end_scope();
+        assert_eq!(EvalResult::Nil, env.get(&identifier("a")).unwrap());
+
+        let error = env.assign(&identifier("c"), EvalResult::Nil).unwrap_err();
+        assert_eq!(ErrorKind::EvalError, error.kind());
+        assert_eq!(
+            "[line 3] RuntimeError EvalError: Undefined variable 'c'",
+            error.to_string()
+        );
+    }
+}
@@ -1,55 +1,163 @@
 use crate::errors::ErrorKind;
 use crate::errors::ReloxError;
+use crate::eval::environment::Environment;
 use crate::grammar::expr::Expr;
 use crate::grammar::expr::ExprLiteral;
+use crate::grammar::stmt::Stmt;
 use crate::token::token_type::TokenType;
 use crate::token::Token;
 use crate::Result;
+use std::cell::RefCell;
 use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum EvalResult {
-    Number(f32),
+    Integer(i64),
+    Float(f64),
     String(String),
     Bool(bool),
+    // Lists are shared, assigning one to another variable doesn't copy it
+    List(Rc<RefCell<Vec<EvalResult>>>),
     Nil,
 }
 
+impl EvalResult {
+    /// Lox truthiness: only `nil` and `false` are falsey
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, EvalResult::Nil | EvalResult::Bool(false))
+    }
+}
+
 impl fmt::Display for EvalResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            EvalResult::Number(val) => write!(f, "{}", val),
+            EvalResult::Integer(val) => write!(f, "{}", val),
+            // Whole floats keep their fraction, like in the VM
+            EvalResult::Float(val) => write!(f, "{:?}", val),
             EvalResult::String(val) => write!(f, "{}", val),
             EvalResult::Bool(val) => write!(f, "{}", val),
+            EvalResult::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
             EvalResult::Nil => write!(f, "nil"),
         }
     }
 }
 
 pub trait Eval {
-    fn eval(&self) -> Result<EvalResult>;
+    fn eval(&self, env: &mut Environment) -> Result<EvalResult>;
+}
+
+/// Runs a program and returns the value of its last statement when it's an expression
+/// statement, `nil` otherwise. That's what the REPL prints.
+pub fn interpret(stmts: &[Stmt], env: &mut Environment) -> Result<EvalResult> {
+    let mut result = EvalResult::Nil;
+
+    for stmt in stmts {
+        result = stmt.eval(env)?;
+    }
+
+    Ok(result)
+}
+
+impl Eval for Stmt {
+    fn eval(&self, env: &mut Environment) -> Result<EvalResult> {
+        match self {
+            Stmt::Expression(expr) => expr.eval(env),
+            Stmt::Print(expr) => {
+                println!("{}", expr.eval(env)?);
+                Ok(EvalResult::Nil)
+            }
+            Stmt::Var(name, initializer) => {
+                let value = match initializer {
+                    Some(expr) => expr.eval(env)?,
+                    None => EvalResult::Nil,
+                };
+                env.define(&name.lexeme, value);
+                Ok(EvalResult::Nil)
+            }
+            Stmt::Block(stmts) => {
+                env.begin_scope();
+                let result = stmts.iter().try_for_each(|stmt| stmt.eval(env).map(|_| ()));
+                env.end_scope();
+                result.map(|_| EvalResult::Nil)
+            }
+        }
+    }
 }
 
 impl Eval for Expr {
-    fn eval(&self) -> Result<EvalResult> {
+    fn eval(&self, env: &mut Environment) -> Result<EvalResult> {
         match self {
-            Expr::Binary(left, token, right) => handle_binary(token, left.eval()?, right.eval()?),
-            Expr::Grouping(val) => val.eval(),
-            Expr::Unary(token, right) => handle_unary(token, right.eval()?),
+            Expr::Binary(left, token, right) => {
+                let left = left.eval(env)?;
+                handle_binary(token, left, right.eval(env)?)
+            }
+            Expr::Grouping(val) => val.eval(env),
+            Expr::Unary(token, right) => handle_unary(token, right.eval(env)?),
             Expr::Literal(ExprLiteral::Nil) => Ok(EvalResult::Nil),
             Expr::Literal(ExprLiteral::Bool(a_bool)) => Ok(EvalResult::Bool(*a_bool)),
-            Expr::Literal(ExprLiteral::Number(num)) => Ok(EvalResult::Number(*num)),
+            Expr::Literal(ExprLiteral::Integer(num)) => Ok(EvalResult::Integer(*num)),
+            Expr::Literal(ExprLiteral::Float(num)) => Ok(EvalResult::Float(*num)),
             Expr::Literal(ExprLiteral::String(a_string)) => {
                 Ok(EvalResult::String(a_string.to_string()))
             }
+            Expr::Stringify(val) => Ok(EvalResult::String(val.eval(env)?.to_string())),
+            Expr::Variable(name) => env.get(name),
+            Expr::Assign(name, value) => {
+                let value = value.eval(env)?;
+                env.assign(name, value.clone())?;
+                Ok(value)
+            }
+            Expr::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.eval(env))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(EvalResult::List(Rc::new(RefCell::new(items))))
+            }
+            Expr::Index(list, bracket, index) => {
+                let list = list.eval(env)?;
+                let index = index.eval(env)?;
+                let items = as_list(bracket, list)?;
+                let index = list_index(bracket, index, items.borrow().len())?;
+                let item = items.borrow()[index].clone();
+                Ok(item)
+            }
+            Expr::IndexAssign(list, bracket, index, value) => {
+                let list = list.eval(env)?;
+                let index = index.eval(env)?;
+                let value = value.eval(env)?;
+                let items = as_list(bracket, list)?;
+                let index = list_index(bracket, index, items.borrow().len())?;
+                items.borrow_mut()[index] = value.clone();
+                Ok(value)
+            }
         }
     }
 }
 
 fn handle_unary(token: &Token, evaled_expr: EvalResult) -> Result<EvalResult> {
     match (token.token_type, evaled_expr) {
-        (TokenType::Minus, EvalResult::Number(the_num)) => Ok(EvalResult::Number(-the_num)),
-        (TokenType::Bang, EvalResult::Bool(a_bool)) => Ok(EvalResult::Bool(!a_bool)),
+        (TokenType::Minus, EvalResult::Integer(the_num)) => match the_num.checked_neg() {
+            Some(negated) => Ok(EvalResult::Integer(negated)),
+            None => build_eval_error(token.line, "Integer overflow".to_string()),
+        },
+        (TokenType::Minus, EvalResult::Float(the_num)) => Ok(EvalResult::Float(-the_num)),
+        (TokenType::Tilde, EvalResult::Integer(the_num)) => Ok(EvalResult::Integer(!the_num)),
+        (TokenType::Tilde, result) => {
+            let message = format!("Operand of '~' must be an integer, got {}", result);
+            build_eval_error(token.line, message)
+        }
+        (TokenType::Bang, result) => Ok(EvalResult::Bool(result.is_falsey())),
         (token_type, result) => {
             build_eval_error(token.line, format!("{:?} {}", token_type, result))
         }
@@ -60,10 +168,22 @@ fn handle_binary(
     evaled_left: EvalResult,
     evaled_right: EvalResult,
 ) -> Result<EvalResult> {
+    if matches!(
+        token.token_type,
+        TokenType::Ampersand
+            | TokenType::Pipe
+            | TokenType::Caret
+            | TokenType::LessLess
+            | TokenType::GreaterGreater
+    ) {
+        return handle_bitwise(token, evaled_left, evaled_right);
+    }
+
+    if let Some(numbers) = numbers(&evaled_left, &evaled_right) {
+        return handle_numbers(token, numbers);
+    }
+
     match (token.token_type, evaled_left, evaled_right) {
-        (TokenType::Plus, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Number(x + y))
-        }
         (TokenType::Plus, EvalResult::String(x), EvalResult::String(ref y)) => {
             Ok(EvalResult::String(x + y))
         }
@@ -71,54 +191,20 @@ fn handle_binary(
             let message = "sum parameters must be both numbers or both strings".to_string();
             build_eval_error(token.line, message)
         }
-        (TokenType::Minus, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Number(x - y))
-        }
         (TokenType::Minus, _, _) => {
             let message =
                 "substraction parameters must be both numbers or both strings".to_string();
             build_eval_error(token.line, message)
         }
-        (TokenType::Star, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Number(x * y))
-        }
         (TokenType::Star, _, _) => {
             let message =
                 "Multiplication parameters must be both numbers or both strings".to_string();
             build_eval_error(token.line, message)
         }
-        (TokenType::Slash, EvalResult::Number(x), EvalResult::Number(y)) => {
-            if y == 0.0 {
-                let message = "division by zero is undefined bro".to_string();
-                build_eval_error(token.line, message)
-            } else {
-                Ok(EvalResult::Number(x / y))
-            }
-        }
         (TokenType::Slash, _, _) => {
             let message = "division parameters must be both numbers or both strings".to_string();
             build_eval_error(token.line, message)
         }
-        (TokenType::Greater, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Bool(x > y))
-        }
-        (TokenType::GreaterEqual, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Bool(x >= y))
-        }
-        (TokenType::Less, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Bool(x < y))
-        }
-        (TokenType::LessEqual, EvalResult::Number(x), EvalResult::Number(y)) => {
-            Ok(EvalResult::Bool(x <= y))
-        }
-        (TokenType::BangEqual, EvalResult::Number(x), EvalResult::Number(y)) => {
-            let val = (x - y).abs() >= std::f32::EPSILON;
-            Ok(EvalResult::Bool(val))
-        }
-        (TokenType::EqualEqual, EvalResult::Number(x), EvalResult::Number(y)) => {
-            let val = (x - y).abs() < std::f32::EPSILON;
-            Ok(EvalResult::Bool(val))
-        }
         (TokenType::EqualEqual, EvalResult::String(x), EvalResult::String(y)) => {
             Ok(EvalResult::Bool(x == y))
         }
@@ -137,6 +223,156 @@ fn handle_binary(
     }
 }
 
+enum Numbers {
+    Integers(i64, i64),
+    Floats(f64, f64),
+}
+
+/// Two integers stay integers, an integer with a float is promoted to a float
+fn numbers(left: &EvalResult, right: &EvalResult) -> Option<Numbers> {
+    match (left, right) {
+        (EvalResult::Integer(x), EvalResult::Integer(y)) => Some(Numbers::Integers(*x, *y)),
+        (EvalResult::Integer(x), EvalResult::Float(y)) => Some(Numbers::Floats(*x as f64, *y)),
+        (EvalResult::Float(x), EvalResult::Integer(y)) => Some(Numbers::Floats(*x, *y as f64)),
+        (EvalResult::Float(x), EvalResult::Float(y)) => Some(Numbers::Floats(*x, *y)),
+        _ => None,
+    }
+}
+
+/// Integer arithmetic is checked and integer division truncates. Floats follow IEEE 754, so
+/// dividing them by zero gives an infinity or NaN.
+fn handle_numbers(token: &Token, numbers: Numbers) -> Result<EvalResult> {
+    let result = match (token.token_type, numbers) {
+        (TokenType::Plus, Numbers::Integers(x, y)) => x.checked_add(y).map(EvalResult::Integer),
+        (TokenType::Minus, Numbers::Integers(x, y)) => x.checked_sub(y).map(EvalResult::Integer),
+        (TokenType::Star, Numbers::Integers(x, y)) => x.checked_mul(y).map(EvalResult::Integer),
+        (TokenType::Slash, Numbers::Integers(_, 0)) => {
+            let message = "Integer division by zero".to_string();
+            return build_eval_error(token.line, message);
+        }
+        (TokenType::Slash, Numbers::Integers(x, y)) => x.checked_div(y).map(EvalResult::Integer),
+        (TokenType::Percent, Numbers::Integers(_, 0)) => {
+            let message = "Integer modulo by zero".to_string();
+            return build_eval_error(token.line, message);
+        }
+        (TokenType::Percent, Numbers::Integers(x, y)) => x.checked_rem(y).map(EvalResult::Integer),
+        (TokenType::StarStar, Numbers::Integers(x, y)) if y < 0 => {
+            Some(EvalResult::Float((x as f64).powf(y as f64)))
+        }
+        (TokenType::StarStar, Numbers::Integers(x, y)) => {
+            checked_pow(x, y).map(EvalResult::Integer)
+        }
+        (TokenType::Plus, Numbers::Floats(x, y)) => Some(EvalResult::Float(x + y)),
+        (TokenType::Minus, Numbers::Floats(x, y)) => Some(EvalResult::Float(x - y)),
+        (TokenType::Star, Numbers::Floats(x, y)) => Some(EvalResult::Float(x * y)),
+        (TokenType::Slash, Numbers::Floats(x, y)) => Some(EvalResult::Float(x / y)),
+        (TokenType::Percent, Numbers::Floats(x, y)) => Some(EvalResult::Float(x % y)),
+        (TokenType::StarStar, Numbers::Floats(x, y)) => Some(EvalResult::Float(x.powf(y))),
+        (token_type, Numbers::Integers(x, y)) => return compare(token, token_type, x, y),
+        (token_type, Numbers::Floats(x, y)) => return compare(token, token_type, x, y),
+    };
+
+    match result {
+        Some(result) => Ok(result),
+        None => build_eval_error(token.line, "Integer overflow".to_string()),
+    }
+}
+
+/// `base ** exp` for a non negative `exp`, `None` when it overflows
+fn checked_pow(base: i64, exp: i64) -> Option<i64> {
+    // Exponents past u32::MAX only fit for the bases 0, 1 and -1, keeping the parity is enough
+    // for them
+    let exp = if exp > u32::MAX as i64 {
+        u32::MAX - 1 + (exp % 2) as u32
+    } else {
+        exp as u32
+    };
+    base.checked_pow(exp)
+}
+
+/// Bitwise operators only work on integers. `<<` drops the bits shifted out and `>>` keeps
+/// the sign.
+fn handle_bitwise(token: &Token, left: EvalResult, right: EvalResult) -> Result<EvalResult> {
+    let (x, y) = match (&left, &right) {
+        (EvalResult::Integer(x), EvalResult::Integer(y)) => (*x, *y),
+        _ => {
+            let message = format!(
+                "Operands of '{}' must be integers, got {} and {}",
+                token.lexeme, left, right
+            );
+            return build_eval_error(token.line, message);
+        }
+    };
+
+    let result = match token.token_type {
+        TokenType::Ampersand => x & y,
+        TokenType::Pipe => x | y,
+        TokenType::Caret => x ^ y,
+        _ if !(0..64).contains(&y) => {
+            let message = format!("Shift amount must be between 0 and 63, got {}", y);
+            return build_eval_error(token.line, message);
+        }
+        TokenType::LessLess => x << y,
+        _ => x >> y,
+    };
+    Ok(EvalResult::Integer(result))
+}
+
+fn compare<T: PartialOrd + fmt::Display>(
+    token: &Token,
+    token_type: TokenType,
+    x: T,
+    y: T,
+) -> Result<EvalResult> {
+    let result = match token_type {
+        TokenType::Greater => x > y,
+        TokenType::GreaterEqual => x >= y,
+        TokenType::Less => x < y,
+        TokenType::LessEqual => x <= y,
+        TokenType::EqualEqual => x == y,
+        TokenType::BangEqual => x != y,
+        token_type => {
+            let message = format!("{:?} can't handle {} {}", token_type, x, y);
+            return build_eval_error(token.line, message);
+        }
+    };
+    Ok(EvalResult::Bool(result))
+}
+
+fn as_list(bracket: &Token, value: EvalResult) -> Result<Rc<RefCell<Vec<EvalResult>>>> {
+    match value {
+        EvalResult::List(items) => Ok(items),
+        _ => ReloxError::new_runtime_error(
+            bracket.line,
+            "Can only index lists".to_string(),
+            ErrorKind::EvalError,
+        ),
+    }
+}
+
+/// Checks the index is a whole number within the bounds of the list
+fn list_index(bracket: &Token, index: EvalResult, len: usize) -> Result<usize> {
+    let index = match index {
+        EvalResult::Integer(index) => index,
+        EvalResult::Float(index) if index.fract() == 0.0 => index as i64,
+        EvalResult::Float(index) => {
+            let message = format!("List index must be a whole number, got {}", index);
+            return ReloxError::new_runtime_error(bracket.line, message, ErrorKind::EvalError);
+        }
+        _ => {
+            let message = "List index must be a number".to_string();
+            return ReloxError::new_runtime_error(bracket.line, message, ErrorKind::EvalError);
+        }
+    };
+
+    if index >= 0 && (index as usize) < len {
+        Ok(index as usize)
+    } else {
+        let message = format!("Index {} out of range for a list of length {}", index, len);
+        ReloxError::new_runtime_error(bracket.line, message, ErrorKind::EvalError)
+    }
+}
+
 fn build_eval_error(line: usize, message: String) -> Result<EvalResult> {
     ReloxError::new_runtime_error(line, message, ErrorKind::EvalError)
 }
@@ -144,7 +380,7 @@ fn build_eval_error(line: usize, message: String) -> Result<EvalResult> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{parser::Parser, Scanner};
+    use crate::{errors::RuntimeError, parser::Parser, Scanner};
 
     #[test]
     fn test_binary_eval() {
@@ -152,13 +388,19 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::Number(3.0), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::Integer(3),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
 
         let scanner = Scanner::new("\"a\" + \"b\"".to_string());
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::String("ab".to_string()), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::String("ab".to_string()),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
     }
 
     #[test]
@@ -167,19 +409,30 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::Number(-1.0), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::Integer(-1),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
 
         let scanner = Scanner::new("-30.0".to_string());
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::Number(-30.0), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::Float(-30.0),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
 
         let scanner = Scanner::new("-true".to_string());
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(ErrorKind::EvalError, res.eval().expect_err("").kind());
+        assert_eq!(
+            ErrorKind::EvalError,
+            interpret(&res, &mut Environment::new())
+                .expect_err("")
+                .kind()
+        );
     }
 
     #[test]
@@ -188,19 +441,32 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::Bool(false), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::Bool(false),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
 
         let scanner = Scanner::new("!false".to_string());
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::Bool(true), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::Bool(true),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
 
-        let scanner = Scanner::new("!2".to_string());
-        let tokens = scanner.scan_tokens().unwrap();
-        let mut parser = Parser::new(tokens);
-        let res = parser.parse().unwrap();
-        assert_eq!(ErrorKind::EvalError, res.eval().expect_err("").kind());
+        for (source, expected) in [("!nil", true), ("!0", false), ("!\"s\"", false)] {
+            let scanner = Scanner::new(source.to_string());
+            let tokens = scanner.scan_tokens().unwrap();
+            let mut parser = Parser::new(tokens);
+            let res = parser.parse().unwrap();
+            assert_eq!(
+                EvalResult::Bool(expected),
+                interpret(&res, &mut Environment::new()).unwrap(),
+                "{}",
+                source
+            );
+        }
     }
 
     #[test]
@@ -209,6 +475,199 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse().unwrap();
-        assert_eq!(EvalResult::Number(1.0), res.eval().unwrap());
+        assert_eq!(
+            EvalResult::Integer(1),
+            interpret(&res, &mut Environment::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_global_variables_eval() {
+        let source = "var x = 1 + 2; var y; x = x * 2; print x; x";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let mut env = Environment::new();
+        assert_eq!(EvalResult::Integer(6), interpret(&stmts, &mut env).unwrap());
+
+        let tokens = Scanner::run_with("y".to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        assert_eq!(EvalResult::Nil, interpret(&stmts, &mut env).unwrap());
+    }
+
+    #[test]
+    fn test_block_scopes_eval() {
+        let source = "var a = 1; { var a = 2; { var b = a + 10; a = b; } print a; } a";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!(EvalResult::Integer(1), result);
+
+        let source = "var a = 1; { var b = 2; var c = 3; a = b * c; } a";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!(EvalResult::Integer(6), result);
+    }
+
+    #[test]
+    fn test_undefined_variable_eval() {
+        let source = "var x = 1;\n{\n var z = 2;\n}\nx = z;";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let mut env = Environment::new();
+
+        if let ReloxError::RuntimeError(RuntimeError {
+            line,
+            message,
+            kind,
+        }) = interpret(&stmts, &mut env).expect_err("should've been an error")
+        {
+            assert_eq!(5, line);
+            assert_eq!("Undefined variable 'z'", message);
+            assert_eq!(ErrorKind::EvalError, kind);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_lists_eval() {
+        let source = "var xs = [1, 2, [3]]; var ys = xs; ys[0] = xs[1] + xs[2][0]; print xs; xs";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!("[5, 2, [3]]", result.to_string());
+
+        let tokens = Scanner::run_with("[]".to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!(EvalResult::List(Rc::new(RefCell::new(Vec::new()))), result);
+    }
+
+    #[test]
+    fn test_integers_and_floats_eval() {
+        let source = "[16777217, 7 / 2, -7 / 2, 7 / 2.0, 1 + 0.5, 2 * 1.0, 1 == 1.0, 2 < 2.5, \
+            0.1 + 0.2 == 0.3, 1.0 / 0, -9223372036854775807 - 1]";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!(
+            "[16777217, 3, -3, 3.5, 1.5, 2.0, true, true, false, inf, -9223372036854775808]",
+            result.to_string()
+        );
+
+        let sources = [
+            ("var a = 1;\na / 0;", "Integer division by zero"),
+            ("var a = 9223372036854775807;\na + 1;", "Integer overflow"),
+            ("var a = -9223372036854775807 - 1;\n-a;", "Integer overflow"),
+            (
+                "var a = -9223372036854775807 - 1;\na / -1;",
+                "Integer overflow",
+            ),
+            ("var a = 4294967296;\na * a;", "Integer overflow"),
+        ];
+
+        for (source, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let stmts = Parser::new(tokens).parse().unwrap();
+            let error = interpret(&stmts, &mut Environment::new()).expect_err("should fail");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_modulo_power_and_bitwise_eval() {
+        let source =
+            "[7 % 3, -7 % 3, 7.5 % 2, 2 ** 10, 2 ** 3 ** 2, -2 ** 2, 2 ** -1, 1 ** 5000000001, \
+            6 & 3, 6 | 3, 6 ^ 3, ~5, 1 << 63, -16 >> 2, 1 + 2 << 1, 12345 & 0xFF == 57]";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!(
+            "[1, -1, 1.5, 1024, 512, -4, 0.5, 1, 2, 7, 5, -6, -9223372036854775808, -4, 6, true]",
+            result.to_string()
+        );
+
+        let sources = [
+            ("var a = 1;\na % 0;", "Integer modulo by zero"),
+            ("var a = 2;\na ** 63;", "Integer overflow"),
+            (
+                "var a = 1.5;\na & 1;",
+                "Operands of '&' must be integers, got 1.5 and 1",
+            ),
+            (
+                "var a = 1;\na << 64;",
+                "Shift amount must be between 0 and 63, got 64",
+            ),
+            (
+                "var a = 1;\na >> -1;",
+                "Shift amount must be between 0 and 63, got -1",
+            ),
+            (
+                "var a = 1.0;\n~a;",
+                "Operand of '~' must be an integer, got 1.0",
+            ),
+        ];
+
+        for (source, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let stmts = Parser::new(tokens).parse().unwrap();
+            let error = interpret(&stmts, &mut Environment::new()).expect_err("should fail");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation_eval() {
+        let source = "var n = 2; var xs = [n, nil]; \"n=${n} ${xs}, ${\"in${n + 1}\"}\\n\"";
+        let tokens = Scanner::run_with(source.to_string()).unwrap();
+        let stmts = Parser::new(tokens).parse().unwrap();
+        let result = interpret(&stmts, &mut Environment::new()).unwrap();
+        assert_eq!(
+            EvalResult::String("n=2 [2, nil], in3\n".to_string()),
+            result
+        );
+    }
+
+    #[test]
+    fn test_list_index_errors_eval() {
+        let sources = [
+            (
+                "var xs = [1];\nxs[1];",
+                "Index 1 out of range for a list of length 1",
+            ),
+            (
+                "var xs = [1];\nxs[-1] = 2;",
+                "Index -1 out of range for a list of length 1",
+            ),
+            (
+                "var xs = [1];\nxs[0.5];",
+                "List index must be a whole number, got 0.5",
+            ),
+            ("var xs = [1];\nxs[\"a\"];", "List index must be a number"),
+            ("var xs = 1;\nxs[0];", "Can only index lists"),
+        ];
+
+        for (source, expected_message) in sources {
+            let tokens = Scanner::run_with(source.to_string()).unwrap();
+            let stmts = Parser::new(tokens).parse().unwrap();
+            let error = interpret(&stmts, &mut Environment::new()).expect_err("should fail");
+            if let ReloxError::RuntimeError(RuntimeError { line, message, .. }) = error {
+                assert_eq!(2, line);
+                assert_eq!(expected_message, message);
+            } else {
+                panic!("Shouldn't have reached this point")
+            }
+        }
     }
 }
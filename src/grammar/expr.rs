@@ -5,7 +5,8 @@ use std::fmt;
 pub enum ExprLiteral {
     Bool(bool),
     String(String),
-    Number(f32),
+    Integer(i64),
+    Float(f64),
     Nil,
 }
 
@@ -15,6 +16,14 @@ pub enum Expr {
     Grouping(Box<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Unary(Token, Box<Expr>), // More efficient ? Binary(Box<(Expr<'a>, Expr<'a>)>, &'a Token),
+    Variable(Token),
+    Assign(Token, Box<Expr>),
+    List(Vec<Expr>),
+    // The token is the closing bracket, used to report errors
+    Index(Box<Expr>, Token, Box<Expr>),
+    IndexAssign(Box<Expr>, Token, Box<Expr>, Box<Expr>),
+    // Converts the value to a string, used by string interpolation
+    Stringify(Box<Expr>),
 }
 
 impl fmt::Display for Expr {
@@ -23,9 +32,24 @@ impl fmt::Display for Expr {
             Expr::Binary(left, token, right) => write!(f, "({} {} {})", left, token.lexeme, right),
             Expr::Grouping(val) => write!(f, "(grouping {})", val),
             Expr::Unary(token, right) => write!(f, "{}{}", token.lexeme, right),
+            Expr::Variable(name) => write!(f, "{}", name.lexeme),
+            Expr::Assign(name, value) => write!(f, "(= {} {})", name.lexeme, value),
+            Expr::List(items) => {
+                write!(f, "(list")?;
+                for item in items {
+                    write!(f, " {}", item)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Index(list, _, index) => write!(f, "(index {} {})", list, index),
+            Expr::IndexAssign(list, _, index, value) => {
+                write!(f, "(= (index {} {}) {})", list, index, value)
+            }
+            Expr::Stringify(val) => write!(f, "(str {})", val),
             Expr::Literal(ExprLiteral::Nil) => write!(f, "null"),
             Expr::Literal(ExprLiteral::Bool(a_bool)) => write!(f, "{}", a_bool),
-            Expr::Literal(ExprLiteral::Number(num)) => write!(f, "{}", num),
+            Expr::Literal(ExprLiteral::Integer(num)) => write!(f, "{}", num),
+            Expr::Literal(ExprLiteral::Float(num)) => write!(f, "{:?}", num),
             Expr::Literal(ExprLiteral::String(a_string)) => write!(f, "\"{}\"", a_string),
         }
     }
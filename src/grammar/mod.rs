@@ -1,19 +1,46 @@
 pub mod expr;
+pub mod stmt;
 
-// expression     → equality ;
+// program        → declaration* EOF ;
+// declaration    → varDecl | statement ;
+// varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
+// statement      → exprStmt | printStmt | block ;
+// exprStmt       → expression ";" ;
+// printStmt      → "print" expression ";" ;
+// block          → "{" declaration* "}" ;
+//
+// expression     → assignment ;
+// assignment     → ( IDENTIFIER | subscript ) "=" assignment
+//                | equality ;
 // equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-// comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+// comparison     → bitOr ( ( ">" | ">=" | "<" | "<=" ) bitOr )* ;
+// bitOr          → bitXor ( "|" bitXor )* ;
+// bitXor         → bitAnd ( "^" bitAnd )* ;
+// bitAnd         → shift ( "&" shift )* ;
+// shift          → term ( ( "<<" | ">>" ) term )* ;
 // term           → factor ( ( "-" | "+" ) factor )* ;
-// factor         → unary ( ( "/" | "*" ) unary )* ;
-// unary          → ( "!" | "-" ) unary
-//                | primary ;
+// factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
+// unary          → ( "!" | "-" | "~" ) unary
+//                | power ;
+// power          → subscript ( "**" unary )? ;
+// subscript      → primary ( "[" expression "]" )* ;
 // primary        → NUMBER | STRING | "true" | "false" | "nil"
-//                | "(" expression ")" ;
+//                | "(" expression ")" | IDENTIFIER
+//                | "[" ( expression ( "," expression )* )? "]"
+//                | ( INTERPOLATION expression )+ STRING ;
 //
 
 // Name	    Operators     Associates
 // Equality	  == !=         Left
 // Comparison	  > >= < <=     Left
+// BitOr	        |           Left
+// BitXor	        ^           Left
+// BitAnd	        &           Left
+// Shift	      << >>         Left
 // Term	       - +          Left
-// Factor	       / *          Left
-// Unary	        ! -         Right
+// Factor	      / * %         Left
+// Unary	       ! - ~        Right
+// Power	        **          Right
+//
+// `**` binds tighter than unary minus, so `-2 ** 2` is `-(2 ** 2)`. It's right-associative,
+// `2 ** 3 ** 2` is `2 ** (3 ** 2)`, and its right operand can be a unary, as in `2 ** -1`.
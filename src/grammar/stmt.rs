@@ -0,0 +1,31 @@
+use crate::grammar::expr::Expr;
+use crate::token::Token;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum Stmt {
+    Expression(Expr),
+    Print(Expr),
+    Var(Token, Option<Expr>),
+    Block(Vec<Stmt>),
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Expression(expr) => write!(f, "{}", expr),
+            Stmt::Print(expr) => write!(f, "(print {})", expr),
+            Stmt::Var(name, None) => write!(f, "(var {})", name.lexeme),
+            Stmt::Var(name, Some(initializer)) => {
+                write!(f, "(var {} {})", name.lexeme, initializer)
+            }
+            Stmt::Block(stmts) => {
+                write!(f, "(block")?;
+                for stmt in stmts {
+                    write!(f, " {}", stmt)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
@@ -1,14 +1,19 @@
 #![warn(missing_debug_implementations)]
 
-use bytecode::value::Value;
-
+use crate::scanner::Scanner;
+use bytecode::chunk::Chunk;
 use bytecode::compiler::Compiler;
+use bytecode::interner::Interner;
 use bytecode::vm::Vm;
-// use crate::eval::interpreted_eval::Eval;
-// use crate::parser::parser::Parser;
-use crate::scanner::Scanner;
+use errors::ReloxError;
+use eval::environment::Environment;
+use eval::interpreted_eval::interpret;
+use grammar::stmt::Stmt;
+use parser::Parser;
 use std::env;
+use std::fs;
 use std::io;
+use std::path::Path;
 use std::process;
 mod bytecode;
 mod errors;
@@ -20,34 +25,74 @@ mod token;
 
 pub type Result<T, E = errors::ReloxError> = std::result::Result<T, E>;
 
+const TREE_WALK_FLAG: &str = "--tree-walk";
+const STRESS_GC_FLAG: &str = "--stress-gc";
+
+#[derive(Debug, Clone, Copy)]
+struct Options {
+    tree_walk: bool,
+    stress_gc: bool,
+}
+
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // The bytecode vm is the default backend, the tree-walk interpreter can be picked with a flag
+    let options = Options {
+        tree_walk: take_flag(&mut args, TREE_WALK_FLAG),
+        stress_gc: take_flag(&mut args, STRESS_GC_FLAG),
+    };
 
-    if args.len() > 2 {
+    if args.len() > 1 {
         println!("Wrong number of arguments");
         process::exit(64);
     }
-    if args.len() == 2 {
-        run_file(&args[1]);
+    if args.len() == 1 {
+        run_file(&args[0], options);
     } else {
-        run_repl()?;
+        run_repl(options)?;
     }
 
     Ok(())
 }
 
-fn run_file(_path: &str) {
-    // let contents = fs::read_to_string(path).expect("Couldn't read file");
-    // run(&contents);
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(position) => {
+            args.remove(position);
+            true
+        }
+        None => false,
+    }
+}
+
+fn run_file(path: &str, options: Options) {
+    let contents = fs::read_to_string(path).expect("Couldn't read file");
+
+    let result = if options.tree_walk {
+        parse(&contents).and_then(|stmts| interpret(&stmts, &mut Environment::new()).map(|_| ()))
+    } else {
+        let mut vm = Vm::new(Chunk::new(), Interner::new(), false);
+        vm.stress_gc(options.stress_gc);
+        compile_file(&contents, Path::new(path), vm.interner())
+            .and_then(|chunk| vm.interpret(chunk).map(|_| ()))
+    };
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
 
-    // if compile error {
-    //     process::exit(65);
-    // } else if runtime error {
-    //     process::exit(70);
-    // }
+        match error {
+            ReloxError::CompilationError(_) => process::exit(65),
+            _ => process::exit(70),
+        }
+    }
 }
 
-fn run_repl() -> Result<()> {
+fn run_repl(options: Options) -> Result<()> {
+    let mut vm = Vm::new(Chunk::new(), Interner::new(), false);
+    vm.stress_gc(options.stress_gc);
+    let mut env = Environment::new();
+
     loop {
         print!("> ");
         io::Write::flush(&mut io::stdout()).expect("flush failed!");
@@ -56,23 +101,36 @@ fn run_repl() -> Result<()> {
         let result = io::stdin().read_line(&mut buffer)?;
         if result == 0 {
             return Ok(());
-        } else {
-            match run(&buffer) {
-                Ok(eval_result) => println!("{}", eval_result),
-                Err(error) => eprintln!("{}", error),
-            };
         }
+
+        let result = if options.tree_walk {
+            parse(&buffer)
+                .and_then(|stmts| interpret(&stmts, &mut env))
+                .map(|eval_result| eval_result.to_string())
+        } else {
+            compile(&buffer, vm.interner())
+                .and_then(|chunk| vm.interpret(chunk))
+                .map(|value| value.to_string())
+        };
+
+        match result {
+            Ok(eval_result) => println!("{}", eval_result),
+            Err(error) => eprintln!("{}", error),
+        };
     }
 }
 
-fn run(input: &str) -> Result<Value> {
+fn parse(input: &str) -> Result<Vec<Stmt>> {
+    let tokens = Scanner::run_with(input.to_string())?;
+    Parser::new(tokens).parse()
+}
+
+fn compile(input: &str, interner: &mut Interner) -> Result<Chunk> {
+    let tokens = Scanner::run_with(input.to_string())?;
+    Compiler::run_with(tokens, interner)
+}
+
+fn compile_file(input: &str, path: &Path, interner: &mut Interner) -> Result<Chunk> {
     let tokens = Scanner::run_with(input.to_string())?;
-    // let mut parser = Parser::run(tokens);
-    // let parse_res = parser.parse().or_else(|error| Err(error.into()));
-    // match parse_res.and_then( |res| res.eval()) {
-    //    Ok(eval_result) => println!("{}", eval_result),
-    //    Err(error) => eprintln!("{}", error)
-    // }
-    let byte_code_chunk = Compiler::run_with(tokens)?;
-    Vm::run_with(byte_code_chunk, false)
+    Compiler::run_file(tokens, interner, path)
 }
@@ -2,20 +2,37 @@ use crate::errors::ErrorKind;
 use crate::errors::ReloxError;
 use crate::grammar::expr::Expr;
 use crate::grammar::expr::ExprLiteral;
+use crate::grammar::stmt::Stmt;
 use crate::token::token_type::TokenType;
 use crate::token::Literal as TokenLiteral;
 use crate::token::Token;
 use crate::Result;
 
-// expression     → equality ;
+// program        → declaration* EOF ;
+// declaration    → varDecl | statement ;
+// varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
+// statement      → exprStmt | printStmt | block ;
+// exprStmt       → expression ";" ;
+// printStmt      → "print" expression ";" ;
+// block          → "{" declaration* "}" ;
+// expression     → assignment ;
+// assignment     → ( IDENTIFIER | subscript ) "=" assignment | equality ;
 // equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-// comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+// comparison     → bitOr ( ( ">" | ">=" | "<" | "<=" ) bitOr )* ;
+// bitOr          → bitXor ( "|" bitXor )* ;
+// bitXor         → bitAnd ( "^" bitAnd )* ;
+// bitAnd         → shift ( "&" shift )* ;
+// shift          → term ( ( "<<" | ">>" ) term )* ;
 // term           → factor ( ( "-" | "+" ) factor )* ;
-// factor         → unary ( ( "/" | "*" ) unary )* ;
-// unary          → ( "!" | "-" ) unary
-//                | primary ;
+// factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
+// unary          → ( "!" | "-" | "~" ) unary
+//                | power ;
+// power          → subscript ( "**" unary )? ;
+// subscript      → primary ( "[" expression "]" )* ;
 // primary        → NUMBER | STRING | "true" | "false" | "nil"
-//                | "(" expression ")" ;
+//                | "(" expression ")" | IDENTIFIER
+//                | "[" ( expression ( "," expression )* )? "]"
+//                | ( INTERPOLATION expression )+ STRING ;
 //
 // Recursive descent parser
 pub struct Parser {
@@ -29,9 +46,80 @@ impl Parser {
         Self { tokens, cursor: 0 }
     }
 
-    pub fn parse(&mut self) -> Result<Expr> {
+    // program → declaration* EOF ;
+    pub fn parse(&mut self) -> Result<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+
+        while !self.check(TokenType::Eof) {
+            stmts.push(self.declaration()?);
+        }
+
+        Ok(stmts)
+    }
+
+    // declaration → varDecl | statement ;
+    fn declaration(&mut self) -> Result<Stmt> {
+        if self.matches(TokenType::Var) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        }
+    }
+
+    // varDecl → "var" IDENTIFIER ( "=" expression )? ";" ;
+    fn var_declaration(&mut self) -> Result<Stmt> {
+        self.consume(TokenType::Identifier, "Expect variable name.")?;
+        let name = self.prev_token()?.clone();
+
+        let initializer = if self.matches(TokenType::Equal) {
+            Some(*self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+        Ok(Stmt::Var(name, initializer))
+    }
+
+    // statement → exprStmt | printStmt | block ;
+    fn statement(&mut self) -> Result<Stmt> {
+        if self.matches(TokenType::Print) {
+            let expr = self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+            Ok(Stmt::Print(*expr))
+        } else if self.matches(TokenType::LeftBrace) {
+            Ok(Stmt::Block(self.block()?))
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    // block → "{" declaration* "}" ;
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            stmts.push(self.declaration()?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
+        Ok(stmts)
+    }
+
+    // exprStmt → expression ";" ;
+    //
+    // Like in the bytecode compiler, the ';' can be left out of a trailing expression so the
+    // REPL can evaluate bare expressions.
+    fn expression_statement(&mut self) -> Result<Stmt> {
         let expr = self.expression()?;
-        Ok(*expr)
+
+        if !self.check(TokenType::Eof) {
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        }
+        Ok(Stmt::Expression(*expr))
     }
 
     fn one_or_many<F>(&mut self, token_types: Vec<TokenType>, mut f: F) -> Result<Box<Expr>>
@@ -53,18 +141,43 @@ impl Parser {
         Ok(left_expr)
     }
 
-    // expression → equality ;
+    // expression → assignment ;
     fn expression(&mut self) -> Result<Box<Expr>> {
-        self.equality()
+        self.assignment()
+    }
+
+    // assignment → ( IDENTIFIER | subscript ) "=" assignment | equality ;
+    fn assignment(&mut self) -> Result<Box<Expr>> {
+        let expr = self.equality()?;
+
+        if self.matches(TokenType::Equal) {
+            let equals = self.prev_token()?.clone();
+            let value = self.assignment()?;
+
+            return match *expr {
+                Expr::Variable(name) => Ok(Box::new(Expr::Assign(name, value))),
+                Expr::Index(list, bracket, index) => {
+                    Ok(Box::new(Expr::IndexAssign(list, bracket, index, value)))
+                }
+                _ => ReloxError::new_compile_error(
+                    equals.line,
+                    "Invalid assignment target.".to_string(),
+                    None,
+                    ErrorKind::ParserError,
+                ),
+            };
+        }
+
+        Ok(expr)
     }
 
     // equality → comparison ( ( "!=" | "==" ) comparison )* ;
     fn equality(&mut self) -> Result<Box<Expr>> {
-        let token_types = vec![TokenType::Bang, TokenType::EqualEqual];
+        let token_types = vec![TokenType::BangEqual, TokenType::EqualEqual];
         self.one_or_many(token_types, |the_self| the_self.comparison())
     }
 
-    // comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
+    // comparison     → bitOr ( ( ">" | ">=" | "<" | "<=" ) bitOr )* ;
     fn comparison(&mut self) -> Result<Box<Expr>> {
         let token_types = vec![
             TokenType::Greater,
@@ -72,6 +185,29 @@ impl Parser {
             TokenType::Less,
             TokenType::LessEqual,
         ];
+        self.one_or_many(token_types, |the_self| the_self.bit_or())
+    }
+
+    // bitOr → bitXor ( "|" bitXor )* ;
+    //
+    // The bitwise operators bind tighter than comparisons, so `x & 1 == 0` is `(x & 1) == 0`
+    fn bit_or(&mut self) -> Result<Box<Expr>> {
+        self.one_or_many(vec![TokenType::Pipe], |the_self| the_self.bit_xor())
+    }
+
+    // bitXor → bitAnd ( "^" bitAnd )* ;
+    fn bit_xor(&mut self) -> Result<Box<Expr>> {
+        self.one_or_many(vec![TokenType::Caret], |the_self| the_self.bit_and())
+    }
+
+    // bitAnd → shift ( "&" shift )* ;
+    fn bit_and(&mut self) -> Result<Box<Expr>> {
+        self.one_or_many(vec![TokenType::Ampersand], |the_self| the_self.shift())
+    }
+
+    // shift → term ( ( "<<" | ">>" ) term )* ;
+    fn shift(&mut self) -> Result<Box<Expr>> {
+        let token_types = vec![TokenType::LessLess, TokenType::GreaterGreater];
         self.one_or_many(token_types, |the_self| the_self.term())
     }
 
@@ -81,28 +217,107 @@ impl Parser {
         self.one_or_many(token_types, |the_self| the_self.factor())
     }
 
-    // factor         → unary ( ( "/" | "*" ) unary )* ;
+    // factor         → unary ( ( "/" | "*" | "%" ) unary )* ;
     fn factor(&mut self) -> Result<Box<Expr>> {
-        let token_types = vec![TokenType::Star, TokenType::Slash];
+        let token_types = vec![TokenType::Star, TokenType::Slash, TokenType::Percent];
         self.one_or_many(token_types, |the_self| the_self.unary())
     }
 
-    // unary → ( "!" | "-" ) unary | primary ;
+    // unary → ( "!" | "-" | "~" ) unary | power ;
     fn unary(&mut self) -> Result<Box<Expr>> {
         if let Some(token) = self.tokens.get(self.cursor) {
             match token.token_type {
-                TokenType::Bang | TokenType::Minus => {
+                TokenType::Bang | TokenType::Minus | TokenType::Tilde => {
                     self.cursor += 1;
-                    Ok(Box::new(Expr::Unary(token.clone(), self.primary()?)))
+                    Ok(Box::new(Expr::Unary(token.clone(), self.unary()?)))
                 }
-                _ => self.primary(),
+                _ => self.power(),
             }
         } else {
-            self.primary()
+            self.power()
+        }
+    }
+
+    // power → subscript ( "**" unary )? ;
+    //
+    // `**` binds tighter than unary operators on its left, `-2 ** 2` is `-(2 ** 2)`. Its right
+    // operand is a unary, which makes it right associative and allows `2 ** -1`.
+    fn power(&mut self) -> Result<Box<Expr>> {
+        let expr = self.subscript()?;
+
+        if self.matches(TokenType::StarStar) {
+            let operator = self.prev_token()?.clone();
+            return Ok(Box::new(Expr::Binary(expr, operator, self.unary()?)));
+        }
+
+        Ok(expr)
+    }
+
+    // subscript → primary ( "[" expression "]" )* ;
+    fn subscript(&mut self) -> Result<Box<Expr>> {
+        let mut expr = self.primary()?;
+
+        while self.matches(TokenType::LeftBracket) {
+            let index = self.expression()?;
+            self.consume(TokenType::RightBracket, "Expect ']' after index.")?;
+            let bracket = self.prev_token()?.clone();
+            expr = Box::new(Expr::Index(expr, bracket, index));
+        }
+
+        Ok(expr)
+    }
+
+    // "[" ( expression ( "," expression )* )? "]"
+    fn list(&mut self) -> Result<Box<Expr>> {
+        let mut items = Vec::new();
+
+        if !self.check(TokenType::RightBracket) {
+            loop {
+                items.push(*self.expression()?);
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list items.")?;
+        Ok(Box::new(Expr::List(items)))
+    }
+
+    // ( INTERPOLATION expression )+ STRING
+    //
+    // The scanner splits `"a ${b} c"` into the `a ` segment, the tokens of `b` and the ` c`
+    // string. It's desugared into `"a " + (str b) + " c"`, dropping empty trailing segments.
+    fn interpolation(&mut self) -> Result<Box<Expr>> {
+        let first = self.prev_token()?.clone();
+        let mut expr = Box::new(Expr::Literal(ExprLiteral::String(string_literal(&first))));
+        let plus = Token::new(TokenType::Plus, "+", None, first.line);
+
+        loop {
+            let value = Box::new(Expr::Stringify(self.expression()?));
+            expr = Box::new(Expr::Binary(expr, plus.clone(), value));
+
+            if !self.matches(TokenType::Interpolation) {
+                self.consume(
+                    TokenType::String,
+                    "Expect '}' after interpolated expression.",
+                )?;
+            }
+
+            let segment = string_literal(self.prev_token()?);
+            if !segment.is_empty() {
+                let segment = Box::new(Expr::Literal(ExprLiteral::String(segment)));
+                expr = Box::new(Expr::Binary(expr, plus.clone(), segment));
+            }
+
+            if self.prev_token()?.token_type == TokenType::String {
+                return Ok(expr);
+            }
         }
     }
 
-    // primary → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" ;
+    // primary → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")" | list
+    //         | interpolation ;
     fn primary(&mut self) -> Result<Box<Expr>> {
         if let Some(token) = self.tokens.get(self.cursor) {
             match (token.token_type, token.literal.as_ref()) {
@@ -121,9 +336,14 @@ impl Parser {
                     let literal = ExprLiteral::Nil;
                     Ok(Box::new(Expr::Literal(literal)))
                 }
+                (TokenType::Number, Some(TokenLiteral::Integer(num_literal))) => {
+                    self.cursor += 1;
+                    let literal = ExprLiteral::Integer(*num_literal);
+                    Ok(Box::new(Expr::Literal(literal)))
+                }
                 (TokenType::Number, Some(TokenLiteral::Double(num_literal))) => {
                     self.cursor += 1;
-                    let literal = ExprLiteral::Number(*num_literal);
+                    let literal = ExprLiteral::Float(*num_literal);
                     Ok(Box::new(Expr::Literal(literal)))
                 }
                 (TokenType::String, Some(TokenLiteral::String(string_literal))) => {
@@ -131,6 +351,10 @@ impl Parser {
                     let literal = ExprLiteral::String(string_literal.to_string());
                     Ok(Box::new(Expr::Literal(literal)))
                 }
+                (TokenType::Identifier, _) => {
+                    self.cursor += 1;
+                    Ok(Box::new(Expr::Variable(token.clone())))
+                }
                 (TokenType::LeftParen, _) => {
                     self.cursor += 1;
                     let expr = self.expression()?;
@@ -140,6 +364,14 @@ impl Parser {
                     )?;
                     Ok(Box::new(Expr::Grouping(expr)))
                 }
+                (TokenType::LeftBracket, _) => {
+                    self.cursor += 1;
+                    self.list()
+                }
+                (TokenType::Interpolation, _) => {
+                    self.cursor += 1;
+                    self.interpolation()
+                }
                 (TokenType::ErrorToken, _) => ReloxError::new_compile_error(
                     token.line,
                     format!("Unrecognized Character {:?}", token.lexeme),
@@ -158,6 +390,29 @@ impl Parser {
         }
     }
 
+    fn check(&self, token_type: TokenType) -> bool {
+        match self.tokens.get(self.cursor) {
+            Some(token) => token.token_type == token_type,
+            None => false,
+        }
+    }
+
+    fn matches(&mut self, token_type: TokenType) -> bool {
+        if self.check(token_type) {
+            self.cursor += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn prev_token(&self) -> Result<&Token> {
+        match self.cursor.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(token) => Ok(token),
+            None => ReloxError::new_fatal_error("Parser Error: out of bounds".to_string()),
+        }
+    }
+
     fn consume(&mut self, token_type: TokenType, message: &str) -> Result<()> {
         if let Some(current_token) = self.tokens.get(self.cursor) {
             if current_token.token_type == token_type {
@@ -174,7 +429,7 @@ impl Parser {
                 let where_at = format!(" at '{}'", current_token.lexeme);
                 ReloxError::new_compile_error(
                     current_token.line,
-                    "".to_string(),
+                    message.to_string(),
                     Some(where_at),
                     ErrorKind::ParserError,
                 )
@@ -185,6 +440,13 @@ impl Parser {
     }
 }
 
+fn string_literal(token: &Token) -> String {
+    match &token.literal {
+        Some(TokenLiteral::String(chars)) => chars.clone(),
+        _ => String::new(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -196,7 +458,7 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse();
-        assert_eq!("(5 == (1 + 2))", format!("{}", res.unwrap(),));
+        assert_eq!("(5 == (1 + 2))", format!("{}", res.unwrap()[0]));
     }
 
     #[test]
@@ -205,7 +467,7 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse();
-        assert_eq!("(5 <= (1 - 2))", format!("{}", res.unwrap(),));
+        assert_eq!("(5 <= (1 - 2))", format!("{}", res.unwrap()[0]));
     }
 
     #[test]
@@ -216,7 +478,7 @@ mod tests {
         let res = parser.parse();
         assert_eq!(
             "(((((false - 2) + 3) + 4) == 2) == (true <= 10))",
-            format!("{}", res.unwrap(),)
+            format!("{}", res.unwrap()[0])
         );
     }
 
@@ -226,7 +488,7 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse();
-        assert_eq!("(\"epppa\" == \"epppa\")", format!("{}", res.unwrap(),));
+        assert_eq!("(\"epppa\" == \"epppa\")", format!("{}", res.unwrap()[0]));
     }
 
     #[test]
@@ -235,7 +497,7 @@ mod tests {
         let tokens = scanner.scan_tokens().unwrap();
         let mut parser = Parser::new(tokens);
         let res = parser.parse();
-        assert_eq!("(1 == (grouping (1 + 2)))", format!("{}", res.unwrap(),));
+        assert_eq!("(1 == (grouping (1 + 2)))", format!("{}", res.unwrap()[0]));
     }
 
     #[test]
@@ -253,4 +515,139 @@ mod tests {
             panic!("Shouldn't have reached this point")
         }
     }
+
+    #[test]
+    fn test_not_equal_and_nested_unary() {
+        let scanner = Scanner::new("1 != --2".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let res = parser.parse();
+        assert_eq!("(1 != --2)", format!("{}", res.unwrap()[0]));
+    }
+
+    #[test]
+    fn test_statements() {
+        let source = "var a = 1;\nvar b;\nprint a + b;\na = b = 3;\n{ var c = a; print c; }";
+        let scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse().unwrap();
+        let printed: Vec<String> = stmts.iter().map(|stmt| format!("{}", stmt)).collect();
+        assert_eq!(
+            vec![
+                "(var a 1)",
+                "(var b)",
+                "(print (a + b))",
+                "(= a (= b 3))",
+                "(block (var c a) (print c))"
+            ],
+            printed
+        );
+    }
+
+    #[test]
+    fn test_statement_errors() {
+        let scanner = Scanner::new("var a = 1\nprint a;".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        if let ReloxError::CompilationError(CompilationError { message, line, .. }) =
+            parser.parse().expect_err("should've been an error")
+        {
+            assert_eq!(2, line);
+            assert_eq!("Expect ';' after variable declaration.", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+
+        let scanner = Scanner::new("{ print 1;".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_err());
+
+        let scanner = Scanner::new("1 + 2 = 3;".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let source = "[];\n[1, \"a\", [nil]];\nxs[0][i + 1];\nxs[0] = -xs[1];";
+        let scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse().unwrap();
+        let printed: Vec<String> = stmts.iter().map(|stmt| format!("{}", stmt)).collect();
+        assert_eq!(
+            vec![
+                "(list)",
+                "(list 1 \"a\" (list null))",
+                "(index (index xs 0) (i + 1))",
+                "(= (index xs 0) -(index xs 1))"
+            ],
+            printed
+        );
+
+        let scanner = Scanner::new("[1, 2;".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        if let ReloxError::CompilationError(CompilationError { message, .. }) =
+            parser.parse().expect_err("should've been an error")
+        {
+            assert_eq!("Expect ']' after list items.", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let source = "\"a ${b} c\";\n\"${1 + 2}${xs[0]}\";\n\"${\"x${y}\"}!\";";
+        let scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse().unwrap();
+        let printed: Vec<String> = stmts.iter().map(|stmt| format!("{}", stmt)).collect();
+        assert_eq!(
+            vec![
+                "((\"a \" + (str b)) + \" c\")",
+                "((\"\" + (str (1 + 2))) + (str (index xs 0)))",
+                "((\"\" + (str (\"x\" + (str y)))) + \"!\")"
+            ],
+            printed
+        );
+
+        let scanner = Scanner::new("\"a ${b c}\";".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        if let ReloxError::CompilationError(CompilationError { message, .. }) =
+            parser.parse().expect_err("should've been an error")
+        {
+            assert_eq!("Expect '}' after interpolated expression.", message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let source =
+            "-2 ** 2;\n2 ** 3 ** -1;\n1 | 2 ^ 3 & 4 << 5 + 6 % 7;\nx & 1 == 0;\n~a[0] * b;";
+        let scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        let stmts = parser.parse().unwrap();
+        let printed: Vec<String> = stmts.iter().map(|stmt| format!("{}", stmt)).collect();
+        assert_eq!(
+            vec![
+                "-(2 ** 2)",
+                "(2 ** (3 ** -1))",
+                "(1 | (2 ^ (3 & (4 << (5 + (6 % 7))))))",
+                "((x & 1) == 0)",
+                "(~(index a 0) * b)"
+            ],
+            printed
+        );
+    }
 }
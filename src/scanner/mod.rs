@@ -3,6 +3,7 @@ use crate::token::token_type::TokenKind;
 use crate::token::token_type::TokenType;
 use crate::token::{Literal, Token};
 use crate::Result;
+use std::num::IntErrorKind;
 
 #[derive(Debug)]
 pub struct Scanner {
@@ -12,7 +13,8 @@ pub struct Scanner {
     source_chars: Vec<char>,
     source_length: usize,
     tokens: Vec<Token>,
-    errors: Vec<ReloxError>,
+    // Open braces of each `${` being scanned, the string resumes on the `}` closing it
+    interpolations: Vec<usize>,
 }
 
 impl Scanner {
@@ -34,12 +36,11 @@ impl Scanner {
             line,
             current_index,
             start,
-            errors: Vec::new(),
+            interpolations: Vec::new(),
         }
     }
 
-    /// Returns a tuple of Tokens and Errors. It uses vector of errors instead of interrupting
-    /// to provide a better experience to (hypotehtical) users by showing all the lexical errors at once.
+    /// Returns the tokens of the source, or the first lexical error found.
     pub fn scan_tokens(mut self) -> Result<Vec<Token>> {
         self.run_scan()?;
         Ok(self.tokens)
@@ -50,16 +51,30 @@ impl Scanner {
             self.start = self.current_index;
 
             // println!("Parsing {}, current_index {}", source_char, self.current_index);
+            if let Some(open_braces) = self.interpolations.last_mut() {
+                match source_char {
+                    '{' => *open_braces += 1,
+                    '}' if *open_braces == 0 => {
+                        self.interpolations.pop();
+                        self.handle_string()?;
+                        self.advance();
+                        continue;
+                    }
+                    '}' => *open_braces -= 1,
+                    _ => {}
+                }
+            }
+
             match TokenType::from_single_char(source_char) {
                 Some((token_type, TokenKind::SingleChar)) => self.add_token(token_type),
                 Some((token_type, TokenKind::OneOrTwoChar)) => self.handle_one_or_two(token_type),
                 Some((token_type, TokenKind::SlashOrComment)) => {
                     self.handle_slash_or_comment(token_type)
                 }
-                Some((TokenType::String, _)) => self.handle_string(),
+                Some((TokenType::String, _)) => self.handle_string()?,
                 Some((TokenType::Skip, _)) => {}
                 Some((TokenType::NewLine, _)) => self.line += 1,
-                Some((TokenType::Number, _)) => self.handle_number(),
+                Some((TokenType::Number, _)) => self.handle_number()?,
                 Some(token_type) => ReloxError::new_compile_error(
                     self.line,
                     format!("Unexpected token {:?}", token_type),
@@ -77,6 +92,16 @@ impl Scanner {
 
             self.advance();
         }
+
+        if !self.interpolations.is_empty() {
+            return ReloxError::new_compile_error(
+                self.line,
+                "Unterminated string interpolation".to_string(),
+                None,
+                LexError,
+            );
+        }
+
         self.tokens
             .push(Token::new(TokenType::Eof, "", None, self.line));
         Ok(())
@@ -156,36 +181,118 @@ impl Scanner {
         }
     }
 
-    fn handle_string(&mut self) {
+    /// Scans a string from its opening quote, or from the `}` closing an interpolated
+    /// expression, up to the closing quote or the next `${`.
+    fn handle_string(&mut self) -> Result<()> {
+        let mut the_string = String::new();
         self.advance();
         while let Some(current_char) = self.current_char() {
-            if current_char == '\n' {
-                self.line += 1;
-                self.advance();
-            } else if current_char != '"' {
-                self.advance();
-            } else {
-                break;
+            match current_char {
+                '"' => break,
+                '\\' => the_string.push(self.handle_escape()?),
+                '$' if self.next_char() == Some('{') => {
+                    let lexeme = self.substring_source(self.start + 1, self.current_index);
+                    self.tokens.push(Token::new(
+                        TokenType::Interpolation,
+                        &lexeme,
+                        Some(Literal::String(the_string)),
+                        self.line,
+                    ));
+                    self.interpolations.push(0);
+                    self.advance();
+                    return Ok(());
+                }
+                '\n' => {
+                    self.line += 1;
+                    the_string.push(current_char);
+                }
+                _ => the_string.push(current_char),
             }
+            self.advance();
         }
 
-        let the_string = &self.substring_source(self.start + 1, self.current_index);
+        let lexeme = self.substring_source(self.start + 1, self.current_index);
 
         if self.is_at_end() {
-            self.tokens.push(Token::new(
-                TokenType::ErrorToken,
-                the_string,
-                None,
-                self.line,
-            ));
+            self.tokens
+                .push(Token::new(TokenType::ErrorToken, &lexeme, None, self.line));
         } else {
             self.tokens.push(Token::new(
                 TokenType::String,
-                the_string,
-                Some(Literal::String(the_string.to_string())),
+                &lexeme,
+                Some(Literal::String(the_string)),
                 self.line,
             ));
         }
+        Ok(())
+    }
+
+    /// Decodes the escape sequence starting at the current backslash and leaves the cursor on
+    /// its last char
+    fn handle_escape(&mut self) -> Result<char> {
+        self.advance();
+        let escaped = match self.current_char() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('$') => '$',
+            Some('u') => return self.handle_unicode_escape(),
+            Some(other) => {
+                return ReloxError::new_compile_error(
+                    self.line,
+                    format!("Invalid escape sequence '\\{}'", other),
+                    None,
+                    LexError,
+                )
+            }
+            None => {
+                return ReloxError::new_compile_error(
+                    self.line,
+                    "Unterminated escape sequence".to_string(),
+                    None,
+                    LexError,
+                )
+            }
+        };
+        Ok(escaped)
+    }
+
+    /// `\u{...}` takes one to six hex digits naming a unicode scalar value
+    fn handle_unicode_escape(&mut self) -> Result<char> {
+        let escape_start = self.current_index - 1;
+        let mut digits = String::new();
+
+        if self.next_char() == Some('{') {
+            self.advance();
+            while let Some(next_char) = self.next_char() {
+                self.advance();
+                if next_char == '}' || next_char == '"' {
+                    break;
+                }
+                digits.push(next_char);
+            }
+        }
+
+        let valid = self.current_char() == Some('}')
+            && (1..=6).contains(&digits.len())
+            && digits.chars().all(|digit| digit.is_ascii_hexdigit());
+        let code_point = u32::from_str_radix(&digits, 16).ok().filter(|_| valid);
+        match code_point.and_then(char::from_u32) {
+            Some(escaped) => Ok(escaped),
+            None => ReloxError::new_compile_error(
+                self.line,
+                format!(
+                    "Invalid unicode escape '{}'",
+                    self.substring_source(escape_start, self.current_index + 1)
+                ),
+                None,
+                LexError,
+            ),
+        }
     }
 
     fn add_error_token(&mut self) {
@@ -199,35 +306,55 @@ impl Scanner {
         }
     }
 
-    fn handle_number(&mut self) {
+    /// Numbers are decimals with an optional fraction and exponent, like `2.5E-10`, or `0x`
+    /// hex and `0b` binary integers. Letters and underscores right after a number are part of
+    /// it, so `0x` or `12ab` are reported instead of splitting them into several tokens.
+    fn handle_number(&mut self) -> Result<()> {
+        let is_decimal = !(self.current_char() == Some('0')
+            && matches!(self.next_char(), Some('x' | 'X' | 'b' | 'B')));
         let mut got_a_dot = false;
+        let mut got_an_exponent = false;
 
         while let Some(next_char) = self.next_char() {
-            if next_char.is_digit(10) {
+            let after_next_char = self.source_chars.get(self.current_index + 2);
+            let current_char = self.current_char();
+
+            if next_char.is_ascii_alphanumeric() || next_char == '_' {
+                got_an_exponent |= is_decimal && matches!(next_char, 'e' | 'E');
                 self.advance();
-            } else if next_char == '.' && !got_a_dot {
+            } else if next_char == '.'
+                && is_decimal
+                && !got_a_dot
+                && !got_an_exponent
+                && after_next_char.is_some_and(char::is_ascii_digit)
+            {
+                // 11. is a number followed by a dot
                 got_a_dot = true;
                 self.advance();
+            } else if matches!(next_char, '+' | '-')
+                && is_decimal
+                && matches!(current_char, Some('e' | 'E'))
+            {
+                self.advance();
             } else {
                 break;
             }
         }
 
-        // if the last char is a dot it means that it's  a number like 11.
-        // so we retreat so the dot gets parsed as its own
-        if let Some('.') = self.current_char() {
-            self.retreat();
-        }
-
         let numstr = &self.substring_source(self.start, self.current_index + 1);
-        let num: Result<f32, _> = numstr.parse();
 
-        self.tokens.push(Token::new(
-            TokenType::Number,
-            numstr,
-            num.ok().map(Literal::Double),
-            self.line,
-        ));
+        match parse_number(numstr) {
+            Ok(literal) => {
+                self.tokens.push(Token::new(
+                    TokenType::Number,
+                    numstr,
+                    Some(literal),
+                    self.line,
+                ));
+                Ok(())
+            }
+            Err(message) => ReloxError::new_compile_error(self.line, message, None, LexError),
+        }
     }
 
     fn substring_source(&self, start: usize, end: usize) -> String {
@@ -261,6 +388,44 @@ impl Scanner {
     }
 }
 
+/// Parses the lexeme of a number. Decimals with a fraction or an exponent are floats, the
+/// rest are 64 bit integers. Underscores can only go between two digits.
+fn parse_number(lexeme: &str) -> std::result::Result<Literal, String> {
+    let malformed = || format!("Malformed number literal '{}'", lexeme);
+    let (radix, digits) = match lexeme.get(..2) {
+        Some("0x" | "0X") => (16, &lexeme[2..]),
+        Some("0b" | "0B") => (2, &lexeme[2..]),
+        _ => (10, lexeme),
+    };
+
+    let chars: Vec<char> = digits.chars().collect();
+    let is_digit = |index: Option<usize>| {
+        index
+            .and_then(|index| chars.get(index))
+            .is_some_and(|digit| digit.is_digit(radix))
+    };
+    let well_separated = (0..chars.len())
+        .filter(|index| chars[*index] == '_')
+        .all(|index| is_digit(index.checked_sub(1)) && is_digit(Some(index + 1)));
+    if !well_separated {
+        return Err(malformed());
+    }
+
+    let digits: String = chars.iter().filter(|digit| **digit != '_').collect();
+    if radix == 10 && digits.contains(['.', 'e', 'E']) {
+        return digits.parse().map(Literal::Double).map_err(|_| malformed());
+    }
+
+    match i64::from_str_radix(&digits, radix) {
+        Ok(num) => Ok(Literal::Integer(num)),
+        Err(error) if *error.kind() == IntErrorKind::PosOverflow => Err(format!(
+            "Integer literal '{}' doesn't fit in 64 bits",
+            lexeme
+        )),
+        Err(_) => Err(malformed()),
+    }
+}
+
 #[cfg(test)]
 #[path = "./test.rs"]
 mod test;
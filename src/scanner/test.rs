@@ -1,4 +1,5 @@
 use super::*;
+use crate::errors::CompilationError;
 use crate::token::token_type::*;
 
 #[test]
@@ -23,6 +24,19 @@ fn single_chars() {
     assert_eq!(*tokens, result);
 }
 
+#[test]
+fn brackets() {
+    let scanner = Scanner::new("[1]".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::LeftBracket, "[", None, 1),
+        Token::new(TokenType::Number, "1", Some(Literal::Integer(1)), 1),
+        Token::new(TokenType::RightBracket, "]", None, 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
 #[test]
 fn multiple_char() {
     let scanner = Scanner::new("!<// blah blah blah".to_string());
@@ -78,7 +92,7 @@ fn numbers() {
     let scanner = Scanner::new("11".to_string());
     let tokens = scanner.scan_tokens().unwrap();
     let result = vec![
-        Token::new(TokenType::Number, "11", Some(Literal::Double(11.0)), 1),
+        Token::new(TokenType::Number, "11", Some(Literal::Integer(11)), 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
     assert_eq!(*tokens, result);
@@ -94,7 +108,7 @@ fn numbers() {
     let scanner = Scanner::new("11.".to_string());
     let tokens = scanner.scan_tokens().unwrap();
     let result = vec![
-        Token::new(TokenType::Number, "11", Some(Literal::Double(11.0)), 1),
+        Token::new(TokenType::Number, "11", Some(Literal::Integer(11)), 1),
         Token::new(TokenType::Dot, ".", None, 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
@@ -105,7 +119,7 @@ fn numbers() {
     let result = vec![
         Token::new(TokenType::Number, "11.12", Some(Literal::Double(11.12)), 1),
         Token::new(TokenType::Dot, ".", None, 1),
-        Token::new(TokenType::Number, "11", Some(Literal::Double(11.0)), 1),
+        Token::new(TokenType::Number, "11", Some(Literal::Integer(11)), 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
 
@@ -120,12 +134,80 @@ fn numbers() {
     assert_eq!(*tokens, result);
 }
 
+#[test]
+fn number_literal_syntax() {
+    let scanner = Scanner::new("0xFF 0XdE_aD 0b1010 1e-3 2.5E10 1_000.5 1e+2.5".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::Number, "0xFF", Some(Literal::Integer(255)), 1),
+        Token::new(
+            TokenType::Number,
+            "0XdE_aD",
+            Some(Literal::Integer(57005)),
+            1,
+        ),
+        Token::new(TokenType::Number, "0b1010", Some(Literal::Integer(10)), 1),
+        Token::new(TokenType::Number, "1e-3", Some(Literal::Double(0.001)), 1),
+        Token::new(
+            TokenType::Number,
+            "2.5E10",
+            Some(Literal::Double(2.5e10)),
+            1,
+        ),
+        Token::new(
+            TokenType::Number,
+            "1_000.5",
+            Some(Literal::Double(1000.5)),
+            1,
+        ),
+        Token::new(TokenType::Number, "1e+2", Some(Literal::Double(100.0)), 1),
+        Token::new(TokenType::Dot, ".", None, 1),
+        Token::new(TokenType::Number, "5", Some(Literal::Integer(5)), 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+
+    let scanner = Scanner::new("0x1e-1".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::Number, "0x1e", Some(Literal::Integer(30)), 1),
+        Token::new(TokenType::Minus, "-", None, 1),
+        Token::new(TokenType::Number, "1", Some(Literal::Integer(1)), 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn malformed_numbers() {
+    let sources = [
+        "0x", "0b", "1e", "2.5E+", "0b102", "0xG", "12ab", "1__0", "1_", "1_.5", "0x_1",
+    ];
+
+    for source in sources {
+        let error = Scanner::run_with(format!("\n{} + 1", source)).unwrap_err();
+        if let ReloxError::CompilationError(CompilationError {
+            line,
+            message,
+            kind,
+            ..
+        }) = error
+        {
+            assert_eq!(2, line);
+            assert_eq!(LexError, kind);
+            assert_eq!(format!("Malformed number literal '{}'", source), message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+}
+
 #[test]
 fn number_followed_by_something() {
     let scanner = Scanner::new("11(".to_string());
     let tokens = scanner.scan_tokens().unwrap();
     let result = vec![
-        Token::new(TokenType::Number, "11", Some(Literal::Double(11.0)), 1),
+        Token::new(TokenType::Number, "11", Some(Literal::Integer(11)), 1),
         Token::new(TokenType::LeftParen, "(", None, 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
@@ -137,9 +219,9 @@ fn number_sum() {
     let scanner = Scanner::new("1 + 2".to_string());
     let tokens = scanner.scan_tokens().unwrap();
     let result = vec![
-        Token::new(TokenType::Number, "1", Some(Literal::Double(1.0)), 1),
+        Token::new(TokenType::Number, "1", Some(Literal::Integer(1)), 1),
         Token::new(TokenType::Plus, "+", None, 1),
-        Token::new(TokenType::Number, "2", Some(Literal::Double(2.0)), 1),
+        Token::new(TokenType::Number, "2", Some(Literal::Integer(2)), 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
     assert_eq!(*tokens, result);
@@ -150,7 +232,7 @@ fn number_with_parens() {
     let scanner = Scanner::new("2)".to_string());
     let tokens = scanner.scan_tokens().unwrap();
     let result = vec![
-        Token::new(TokenType::Number, "2", Some(Literal::Double(2.0)), 1),
+        Token::new(TokenType::Number, "2", Some(Literal::Integer(2)), 1),
         Token::new(TokenType::RightParen, ")", None, 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
@@ -163,9 +245,9 @@ fn number_sum_with_parens() {
     let tokens = scanner.scan_tokens().unwrap();
     let result = vec![
         Token::new(TokenType::LeftParen, "(", None, 1),
-        Token::new(TokenType::Number, "1", Some(Literal::Double(1.0)), 1),
+        Token::new(TokenType::Number, "1", Some(Literal::Integer(1)), 1),
         Token::new(TokenType::Plus, "+", None, 1),
-        Token::new(TokenType::Number, "2", Some(Literal::Double(2.0)), 1),
+        Token::new(TokenType::Number, "2", Some(Literal::Integer(2)), 1),
         Token::new(TokenType::RightParen, ")", None, 1),
         Token::new(TokenType::Eof, "", None, 1),
     ];
@@ -193,7 +275,7 @@ fn mix_of_stuff() {
     let tokens = scanner.scan_tokens().unwrap();
 
     let result = vec![
-        Token::new(TokenType::Number, "42", Some(Literal::Double(42.0)), 1),
+        Token::new(TokenType::Number, "42", Some(Literal::Integer(42)), 1),
         Token::new(
             TokenType::String,
             "sdfsdf",
@@ -236,3 +318,158 @@ fn simple_parens() {
     ];
     assert_eq!(*tokens, result);
 }
+
+#[test]
+fn colon() {
+    let scanner = Scanner::new("{\"a\": 1}".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::LeftBrace, "{", None, 1),
+        Token::new(
+            TokenType::String,
+            "a",
+            Some(Literal::String("a".to_string())),
+            1,
+        ),
+        Token::new(TokenType::Colon, ":", None, 1),
+        Token::new(TokenType::Number, "1", Some(Literal::Integer(1)), 1),
+        Token::new(TokenType::RightBrace, "}", None, 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn loop_keywords() {
+    let scanner = Scanner::new("break continue breaking".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::Break, "break", None, 1),
+        Token::new(TokenType::Continue, "continue", None, 1),
+        Token::new(TokenType::Identifier, "breaking", None, 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn exception_keywords() {
+    let scanner = Scanner::new("try catch finally throw".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::Try, "try", None, 1),
+        Token::new(TokenType::Catch, "catch", None, 1),
+        Token::new(TokenType::Finally, "finally", None, 1),
+        Token::new(TokenType::Throw, "throw", None, 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn import_keyword() {
+    let scanner = Scanner::new("import \"lib.lox\";".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::Import, "import", None, 1),
+        Token::new(
+            TokenType::String,
+            "lib.lox",
+            Some(Literal::String("lib.lox".to_string())),
+            1,
+        ),
+        Token::new(TokenType::Semicolon, ";", None, 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn string_escapes() {
+    let scanner = Scanner::new(r#""a\n\t\"\\\$\u{1F600}\u{e9}$""#.to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(
+            TokenType::String,
+            r#"a\n\t\"\\\$\u{1F600}\u{e9}$"#,
+            Some(Literal::String("a\n\t\"\\$\u{1F600}\u{e9}$".to_string())),
+            1,
+        ),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn string_escape_errors() {
+    let sources = [
+        (r#""a\qb""#, "Invalid escape sequence '\\q'"),
+        (r#""\u{}""#, "Invalid unicode escape '\\u{}'"),
+        (r#""\u{1234567}""#, "Invalid unicode escape '\\u{1234567}'"),
+        (r#""\u{D800}""#, "Invalid unicode escape '\\u{D800}'"),
+        (r#""\u{+41}""#, "Invalid unicode escape '\\u{+41}'"),
+        (r#""\u41""#, "Invalid unicode escape '\\u'"),
+        (r#""\u{41""#, "Invalid unicode escape '\\u{41\"'"),
+        ("\n\"a ${b\n", "Unterminated string interpolation"),
+    ];
+
+    for (source, expected_message) in sources {
+        let error = Scanner::run_with(source.to_string()).unwrap_err();
+        if let ReloxError::CompilationError(CompilationError { message, kind, .. }) = error {
+            assert_eq!(LexError, kind);
+            assert_eq!(expected_message, message);
+        } else {
+            panic!("Shouldn't have reached this point")
+        }
+    }
+}
+
+#[test]
+fn string_interpolation() {
+    let scanner = Scanner::new(r#""a ${b} ${ {"c": "${d}"} }\${e}""#.to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let string = |token_type, lexeme: &str, chars: &str| {
+        Token::new(
+            token_type,
+            lexeme,
+            Some(Literal::String(chars.to_string())),
+            1,
+        )
+    };
+    let result = vec![
+        string(TokenType::Interpolation, "a ", "a "),
+        Token::new(TokenType::Identifier, "b", None, 1),
+        string(TokenType::Interpolation, " ", " "),
+        Token::new(TokenType::LeftBrace, "{", None, 1),
+        string(TokenType::String, "c", "c"),
+        Token::new(TokenType::Colon, ":", None, 1),
+        string(TokenType::Interpolation, "", ""),
+        Token::new(TokenType::Identifier, "d", None, 1),
+        string(TokenType::String, "", ""),
+        Token::new(TokenType::RightBrace, "}", None, 1),
+        string(TokenType::String, r"\${e}", "${e}"),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
+
+#[test]
+fn arithmetic_and_bitwise_operators() {
+    let scanner = Scanner::new("% ** * & | ^ ~ << >> < >".to_string());
+    let tokens = scanner.scan_tokens().unwrap();
+    let result = vec![
+        Token::new(TokenType::Percent, "%", None, 1),
+        Token::new(TokenType::StarStar, "**", None, 1),
+        Token::new(TokenType::Star, "*", None, 1),
+        Token::new(TokenType::Ampersand, "&", None, 1),
+        Token::new(TokenType::Pipe, "|", None, 1),
+        Token::new(TokenType::Caret, "^", None, 1),
+        Token::new(TokenType::Tilde, "~", None, 1),
+        Token::new(TokenType::LessLess, "<<", None, 1),
+        Token::new(TokenType::GreaterGreater, ">>", None, 1),
+        Token::new(TokenType::Less, "<", None, 1),
+        Token::new(TokenType::Greater, ">", None, 1),
+        Token::new(TokenType::Eof, "", None, 1),
+    ];
+    assert_eq!(*tokens, result);
+}
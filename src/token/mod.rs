@@ -13,7 +13,8 @@ pub struct Token {
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Literal {
-    Double(f32),
+    Integer(i64),
+    Double(f64),
     String(String),
 }
 
@@ -14,41 +14,61 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    Star,
+    StarStar,
 
     // SingleChar
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
     Semicolon,
-    Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // Literals.
     Identifier,
     String,
+    // A string segment followed by `${`, the string goes on after the expression's `}`
+    Interpolation,
     Number,
 
     //
 
     // Keywords.
     And,
+    Break,
+    Catch,
     Class,
+    Continue,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -75,12 +95,19 @@ impl TokenType {
         char_to_enum.insert(')', (TokenType::RightParen, TokenKind::SingleChar));
         char_to_enum.insert('{', (TokenType::LeftBrace, TokenKind::SingleChar));
         char_to_enum.insert('}', (TokenType::RightBrace, TokenKind::SingleChar));
+        char_to_enum.insert('[', (TokenType::LeftBracket, TokenKind::SingleChar));
+        char_to_enum.insert(']', (TokenType::RightBracket, TokenKind::SingleChar));
         char_to_enum.insert(',', (TokenType::Comma, TokenKind::SingleChar));
+        char_to_enum.insert(':', (TokenType::Colon, TokenKind::SingleChar));
         char_to_enum.insert('.', (TokenType::Dot, TokenKind::SingleChar));
         char_to_enum.insert('-', (TokenType::Minus, TokenKind::SingleChar));
         char_to_enum.insert('+', (TokenType::Plus, TokenKind::SingleChar));
         char_to_enum.insert(';', (TokenType::Semicolon, TokenKind::SingleChar));
-        char_to_enum.insert('*', (TokenType::Star, TokenKind::SingleChar));
+        char_to_enum.insert('%', (TokenType::Percent, TokenKind::SingleChar));
+        char_to_enum.insert('&', (TokenType::Ampersand, TokenKind::SingleChar));
+        char_to_enum.insert('|', (TokenType::Pipe, TokenKind::SingleChar));
+        char_to_enum.insert('^', (TokenType::Caret, TokenKind::SingleChar));
+        char_to_enum.insert('~', (TokenType::Tilde, TokenKind::SingleChar));
 
         // One or More Chars
         //
@@ -88,6 +115,7 @@ impl TokenType {
         char_to_enum.insert('=', (TokenType::Equal, TokenKind::OneOrTwoChar));
         char_to_enum.insert('<', (TokenType::Less, TokenKind::OneOrTwoChar));
         char_to_enum.insert('>', (TokenType::Greater, TokenKind::OneOrTwoChar));
+        char_to_enum.insert('*', (TokenType::Star, TokenKind::OneOrTwoChar));
 
         // Slash
         char_to_enum.insert('/', (TokenType::Slash, TokenKind::SlashOrComment));
@@ -127,6 +155,9 @@ impl TokenType {
         str_to_enum.insert("==".to_string(), TokenType::EqualEqual);
         str_to_enum.insert("<=".to_string(), TokenType::LessEqual);
         str_to_enum.insert(">=".to_string(), TokenType::GreaterEqual);
+        str_to_enum.insert("<<".to_string(), TokenType::LessLess);
+        str_to_enum.insert(">>".to_string(), TokenType::GreaterGreater);
+        str_to_enum.insert("**".to_string(), TokenType::StarStar);
 
         let mut as_str = String::new();
         as_str.push(first_char);
@@ -142,19 +173,26 @@ impl TokenType {
     pub fn keyword(a_string: &str) -> Option<TokenType> {
         let mut keywords = HashMap::<String, TokenType>::new();
         keywords.insert(String::from("and"), TokenType::And);
+        keywords.insert(String::from("break"), TokenType::Break);
+        keywords.insert(String::from("catch"), TokenType::Catch);
         keywords.insert(String::from("class"), TokenType::Class);
+        keywords.insert(String::from("continue"), TokenType::Continue);
         keywords.insert(String::from("else"), TokenType::Else);
         keywords.insert(String::from("false"), TokenType::False);
+        keywords.insert(String::from("finally"), TokenType::Finally);
         keywords.insert(String::from("for"), TokenType::For);
         keywords.insert(String::from("fun"), TokenType::Fun);
         keywords.insert(String::from("if"), TokenType::If);
+        keywords.insert(String::from("import"), TokenType::Import);
         keywords.insert(String::from("nil"), TokenType::Nil);
         keywords.insert(String::from("or"), TokenType::Or);
         keywords.insert(String::from("print"), TokenType::Print);
         keywords.insert(String::from("return"), TokenType::Return);
         keywords.insert(String::from("super"), TokenType::Super);
         keywords.insert(String::from("this"), TokenType::This);
+        keywords.insert(String::from("throw"), TokenType::Throw);
         keywords.insert(String::from("true"), TokenType::True);
+        keywords.insert(String::from("try"), TokenType::Try);
         keywords.insert(String::from("var"), TokenType::Var);
         keywords.insert(String::from("while"), TokenType::While);
 